@@ -0,0 +1,36 @@
+use anyhow::{anyhow, Result};
+use std::time::Instant;
+
+use tiledir::{encode, ImageOps};
+
+fn main() -> Result<()> {
+    let usage = "usage: input-file.png [quality]";
+    let input = std::env::args().nth(1).ok_or_else(|| anyhow!(usage))?;
+    let quality = match std::env::args().nth(2) {
+        Some(quality) => quality.parse()?,
+        None => ImageOps::default().quality,
+    };
+
+    // a tile's worth from the middle, where there's most likely something
+    let img = image::open(input)?;
+    let wh = 256.min(img.width()).min(img.height());
+    let tile = img.crop_imm((img.width() - wh) / 2, (img.height() - wh) / 2, wh, wh);
+
+    println!("{wh}px tile at quality {quality}");
+    println!("speed    encode ms    bytes");
+    for speed in 0..=10 {
+        let img_ops = ImageOps {
+            speed,
+            quality,
+            // it's the timing that matters, not the depth
+            force_8bit: true,
+            ..ImageOps::default()
+        };
+        let mut data = Vec::new();
+        let start = Instant::now();
+        encode(&tile, &img_ops, &mut data)?;
+        let ms = start.elapsed().as_secs_f64() * 1000.;
+        println!("{speed:>5} {ms:>12.1} {:>8}", data.len());
+    }
+    Ok(())
+}
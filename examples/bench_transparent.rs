@@ -0,0 +1,49 @@
+use anyhow::{anyhow, Result};
+use image::{DynamicImage, Rgba, RgbaImage};
+use std::time::Instant;
+
+use tiledir::{is_entirely_transparent, is_region_transparent, TileOptions};
+
+fn main() -> Result<()> {
+    let usage = "usage: [base-size]";
+    let wh = match std::env::args().nth(1) {
+        Some(wh) => wh.parse().map_err(|_| anyhow!(usage))?,
+        None => TileOptions::default().base_size,
+    };
+
+    let opaque = DynamicImage::ImageRgba8(RgbaImage::from_pixel(wh, wh, Rgba([0, 50, 200, 255])));
+    let clear = DynamicImage::new_rgba8(wh, wh);
+    // only the very last pixel shows, so nothing can stop early
+    let mut speck = RgbaImage::new(wh, wh);
+    speck.put_pixel(wh - 1, wh - 1, Rgba([0, 0, 0, 255]));
+    let speck = DynamicImage::ImageRgba8(speck);
+    let deep_opaque = DynamicImage::ImageRgba16(opaque.to_rgba16());
+
+    let time = |name: &str, check: &dyn Fn() -> bool| {
+        let runs = 20;
+        let start = Instant::now();
+        let mut result = false;
+        for _ in 0..runs {
+            result = std::hint::black_box(check());
+        }
+        let us = start.elapsed().as_secs_f64() * 1e6 / f64::from(runs);
+        println!("{name:<30} {us:>10.1} {result:>7}");
+    };
+
+    println!("{wh}x{wh} base");
+    println!("{:<30} {:>10} {:>7}", "check", "us/run", "result");
+    time("opaque", &|| is_entirely_transparent(&opaque));
+    time("opaque, 16-bit", &|| is_entirely_transparent(&deep_opaque));
+    time("opaque, one tile", &|| {
+        is_region_transparent(&opaque, wh / 2, wh / 2, wh / 16, wh / 16)
+    });
+    time("clear", &|| is_entirely_transparent(&clear));
+    time("one pixel showing, at the end", &|| {
+        is_entirely_transparent(&speck)
+    });
+    // what it used to cost, for anything not already 8-bit rgba: a copy, then the scan
+    time("opaque, 16-bit, copied", &|| {
+        deep_opaque.to_rgba8().pixels().all(|p| p.0[3] == 0)
+    });
+    Ok(())
+}
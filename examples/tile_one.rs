@@ -0,0 +1,27 @@
+use anyhow::{anyhow, Result};
+use std::path::Path;
+
+use tiledir::{tile_base, TileOptions};
+
+fn main() -> Result<()> {
+    let usage = "usage: input-file.png x y output-dir [zoom]";
+    let arg = |n: usize| std::env::args().nth(n).ok_or_else(|| anyhow!(usage));
+    let input = arg(1)?;
+    let x: u32 = arg(2)?.parse()?;
+    let y: u32 = arg(3)?.parse()?;
+    let output = arg(4)?;
+    // defaults to the shallowest level with room for x, y
+    let zoom = match std::env::args().nth(5) {
+        Some(zoom) => zoom.parse()?,
+        None => u32::BITS - x.max(y).leading_zeros(),
+    };
+
+    let img = image::open(input)?;
+    let opts = TileOptions {
+        base_size: img.width(),
+        ..TileOptions::default()
+    };
+    let written = tile_base(&img, (x, y), zoom, Path::new(&output), &opts)?;
+    println!("wrote {written} tiles, from zoom level {zoom}");
+    Ok(())
+}
@@ -0,0 +1,136 @@
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, ImageBuffer, Pixel};
+
+use crate::resize_rect;
+
+/// a base with strips of its eight neighbours around it, so a tile on the base's edge can be
+/// scaled with the pixels just past that edge, as one in the middle is, instead of clamping
+pub(crate) struct Bled {
+    canvas: DynamicImage,
+    /// width of the strips, in base pixels
+    margin: u32,
+    base_wh: u32,
+    /// which neighbours there are, by `[dy + 1][dx + 1]`; the middle is the base itself
+    have: [[bool; 3]; 3],
+}
+
+type Strips = Vec<(DynamicImage, (u32, u32))>;
+
+impl Bled {
+    /// `neighbour(dx, dy)` is the base that far away, if there is one, and it's the same size
+    pub(crate) fn new(
+        base: &DynamicImage,
+        margin: u32,
+        mut neighbour: impl FnMut(i64, i64) -> Option<DynamicImage>,
+    ) -> Bled {
+        let base_wh = base.width();
+        // the part of a neighbour next to the base, and where that goes on the canvas
+        let span = |d: i64| match d {
+            -1 => (base_wh - margin, margin, 0),
+            0 => (0, base_wh, margin),
+            _ => (0, margin, margin + base_wh),
+        };
+        let mut have = [[false; 3]; 3];
+        have[1][1] = true;
+        let mut strips = Strips::new();
+        for (dy, dx) in (-1..=1).flat_map(|dy| (-1..=1).map(move |dx| (dy, dx))) {
+            if (dx, dy) == (0, 0) {
+                continue;
+            }
+            let Some(img) = neighbour(dx, dy) else {
+                continue;
+            };
+            let ((sx, w, tx), (sy, h, ty)) = (span(dx), span(dy));
+            strips.push((img.crop_imm(sx, sy, w, h), (tx, ty)));
+            have[(dy + 1) as usize][(dx + 1) as usize] = true;
+        }
+
+        let canvas = match base {
+            DynamicImage::ImageRgb8(b) => {
+                DynamicImage::ImageRgb8(surround(b, margin, &strips, DynamicImage::to_rgb8))
+            }
+            DynamicImage::ImageRgba8(b) => {
+                DynamicImage::ImageRgba8(surround(b, margin, &strips, DynamicImage::to_rgba8))
+            }
+            DynamicImage::ImageRgb16(b) => {
+                DynamicImage::ImageRgb16(surround(b, margin, &strips, DynamicImage::to_rgb16))
+            }
+            DynamicImage::ImageRgba16(b) => {
+                DynamicImage::ImageRgba16(surround(b, margin, &strips, DynamicImage::to_rgba16))
+            }
+            _ => DynamicImage::ImageRgba8(surround(
+                &base.to_rgba8(),
+                margin,
+                &strips,
+                DynamicImage::to_rgba8,
+            )),
+        };
+        Bled {
+            canvas,
+            margin,
+            base_wh,
+            have,
+        }
+    }
+
+    /// the `step` square at `cx`, `cy` in the base, scaled to `wh` with `bleed` output pixels
+    /// of context each side, where there is any
+    pub(crate) fn tile(
+        &self,
+        (cx, cy, step): (u32, u32, u32),
+        wh: u32,
+        bleed: u32,
+        filter: FilterType,
+        linear: bool,
+    ) -> DynamicImage {
+        let have = |dx: i64, dy: i64| self.have[(dy + 1) as usize][(dx + 1) as usize];
+        // which neighbour, if any, each side of the tile would reach into
+        let before = |c: u32| if c == 0 { -1 } else { 0 };
+        let after = |c: u32| if c + step == self.base_wh { 1 } else { 0 };
+        let (l, r, t, b) = (before(cx), after(cx), before(cy), after(cy));
+        let left = have(l, 0);
+        let right = have(r, 0);
+        let mut top = have(0, t);
+        let mut bottom = have(0, b);
+        // a missing corner can't be made up, so go without context on that side instead
+        for (dx, used) in [(l, left), (r, right)] {
+            if used && dx != 0 {
+                top &= t == 0 || have(dx, t);
+                bottom &= b == 0 || have(dx, b);
+            }
+        }
+        let [left, right, top, bottom] = [left, right, top, bottom].map(u32::from);
+
+        let m = bleed * step / wh;
+        let window = self.canvas.crop_imm(
+            self.margin + cx - left * m,
+            self.margin + cy - top * m,
+            step + (left + right) * m,
+            step + (top + bottom) * m,
+        );
+        let scaled = resize_rect(
+            &window,
+            wh + (left + right) * bleed,
+            wh + (top + bottom) * bleed,
+            filter,
+            linear,
+        );
+        scaled.crop_imm(left * bleed, top * bleed, wh, wh)
+    }
+}
+
+/// `base` in the middle of a canvas `margin` bigger each side, with the strips copied around it
+fn surround<P: Pixel>(
+    base: &ImageBuffer<P, Vec<P::Subpixel>>,
+    margin: u32,
+    strips: &Strips,
+    convert: fn(&DynamicImage) -> ImageBuffer<P, Vec<P::Subpixel>>,
+) -> ImageBuffer<P, Vec<P::Subpixel>> {
+    let full = base.width() + 2 * margin;
+    let mut canvas = ImageBuffer::new(full, full);
+    imageops::replace(&mut canvas, base, i64::from(margin), i64::from(margin));
+    for (strip, (x, y)) in strips {
+        imageops::replace(&mut canvas, &convert(strip), i64::from(*x), i64::from(*y));
+    }
+    canvas
+}
@@ -0,0 +1,83 @@
+use anyhow::{anyhow, Context, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use image::DynamicImage;
+
+/// the mean colour of each tile, as a json object of `"z/x/y": [r, g, b, a]`, for viewers to
+/// show while the tile loads
+///
+/// tiles kept from earlier runs aren't looked at again, so their entries are read back from
+/// the existing file; entries for tiles which have since gone are kept too
+pub(crate) struct ColorIndex {
+    colours: Mutex<BTreeMap<(u32, u32, u32), [u8; 4]>>,
+}
+
+impl ColorIndex {
+    /// starting with whatever's in the index at `path`, if there is one
+    pub(crate) fn open(path: &Path) -> Result<ColorIndex> {
+        let colours = match fs::read_to_string(path) {
+            Ok(text) => text.lines().filter_map(parse_line).collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e).with_context(|| anyhow!("reading color index {path:?}")),
+        };
+        Ok(ColorIndex {
+            colours: Mutex::new(colours),
+        })
+    }
+
+    /// note the mean colour of the tile at `zoom`, `x`, `y`, as it's named on disk
+    pub(crate) fn record(&self, zoom: u32, x: u32, y: u32, img: &DynamicImage) {
+        let rgba = img.to_rgba8();
+        let mut sums = [0u64; 4];
+        for pixel in rgba.pixels() {
+            for (sum, channel) in sums.iter_mut().zip(pixel.0) {
+                *sum += u64::from(channel);
+            }
+        }
+        let count = u64::from(rgba.width()) * u64::from(rgba.height());
+        let mean = sums.map(|sum| (sum / count.max(1)) as u8);
+        self.colours
+            .lock()
+            .expect("poisoned")
+            .insert((zoom, x, y), mean);
+    }
+
+    /// replace the index at `path`, atomically
+    pub(crate) fn write(&self, path: &Path) -> Result<()> {
+        let colours = self.colours.lock().expect("poisoned");
+        let mut json = String::from("{\n");
+        for (i, ((zoom, x, y), [r, g, b, a])) in colours.iter().enumerate() {
+            let comma = if i + 1 < colours.len() { "," } else { "" };
+            json.push_str(&format!(
+                "  \"{zoom}/{x}/{y}\": [{r}, {g}, {b}, {a}]{comma}\n"
+            ));
+        }
+        json.push_str("}\n");
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut out = tempfile_fast::Sponge::new_for(path)?;
+        out.write_all(json.as_bytes())?;
+        out.commit()
+            .with_context(|| anyhow!("writing color index to {path:?}"))
+    }
+}
+
+/// a line as `write` makes them; anything else, like the braces, is skipped
+fn parse_line(line: &str) -> Option<((u32, u32, u32), [u8; 4])> {
+    let (key, value) = line.trim().trim_end_matches(',').split_once(": ")?;
+    let mut coords = key.strip_prefix('"')?.strip_suffix('"')?.split('/');
+    let zoom = coords.next()?.parse().ok()?;
+    let x = coords.next()?.parse().ok()?;
+    let y = coords.next()?.parse().ok()?;
+    let mut channels = value.strip_prefix('[')?.strip_suffix(']')?.split(',');
+    let mut mean = [0; 4];
+    for channel in &mut mean {
+        *channel = channels.next()?.trim().parse().ok()?;
+    }
+    Some(((zoom, x, y), mean))
+}
@@ -0,0 +1,89 @@
+use anyhow::{anyhow, Context, Result};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use log::warn;
+
+use crate::write_with_retries;
+
+/// hard links tiles with identical contents to the first one written, instead of writing copies
+///
+/// two identical tiles being written at the same moment may both end up as real files; that's
+/// just a missed saving
+pub(crate) struct Dedup {
+    seen: Mutex<HashMap<u64, PathBuf>>,
+    linked: AtomicUsize,
+    warned: AtomicBool,
+    write_retries: u32,
+}
+
+impl Dedup {
+    /// `write_retries` is for writing the copies; linking isn't retried
+    pub(crate) fn new(write_retries: u32) -> Dedup {
+        Dedup {
+            seen: Mutex::new(HashMap::new()),
+            linked: AtomicUsize::new(0),
+            warned: AtomicBool::new(false),
+            write_retries,
+        }
+    }
+
+    /// tiles which were linked rather than written
+    pub(crate) fn linked(&self) -> usize {
+        self.linked.load(Ordering::SeqCst)
+    }
+
+    /// write `data` to `path`, or link `path` to an earlier tile with the same contents
+    pub(crate) fn write(&self, path: &Path, data: &[u8]) -> Result<()> {
+        let mut hasher = DefaultHasher::new();
+        data.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        let first = self.seen.lock().expect("poisoned").get(&hash).cloned();
+        if let Some(first) = &first {
+            // collisions are unlikely, but cheap to rule out on files this small
+            if fs::read(first).is_ok_and(|existing| existing == data) && self.link(first, path)? {
+                return Ok(());
+            }
+        }
+
+        write_with_retries(path, data, self.write_retries)?;
+        if first.is_none() {
+            self.seen
+                .lock()
+                .expect("poisoned")
+                .entry(hash)
+                .or_insert_with(|| path.to_path_buf());
+        }
+        Ok(())
+    }
+
+    /// false if the filesystem won't, so the caller should write a copy
+    fn link(&self, first: &Path, path: &Path) -> Result<bool> {
+        let parent = path
+            .parent()
+            .ok_or_else(|| anyhow!("expected directory in path name, not {path:?}"))?;
+        fs::create_dir_all(parent).with_context(|| anyhow!("creating directories for {path:?}"))?;
+
+        // linked beside the destination then renamed over it, so an existing tile is replaced
+        // atomically, as with a normal write
+        let temp = path.with_extension("link-tmp");
+        let _ = fs::remove_file(&temp);
+        if let Err(e) = fs::hard_link(first, &temp) {
+            if !self.warned.swap(true, Ordering::SeqCst) {
+                warn!("can't hard link tiles ({e}), so writing copies instead");
+            }
+            return Ok(false);
+        }
+        fs::rename(&temp, path).with_context(|| anyhow!("moving link into place at {path:?}"))?;
+        // renaming onto another link to the same file does nothing, leaving the temporary behind
+        let _ = fs::remove_file(&temp);
+        self.linked.fetch_add(1, Ordering::SeqCst);
+        Ok(true)
+    }
+}
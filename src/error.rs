@@ -0,0 +1,121 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::ops::Deref;
+
+use image::ImageError;
+
+/// why tiling failed, by what a caller might do about it
+#[derive(Debug, thiserror::Error)]
+pub enum TileError {
+    /// the options, or the inputs, can't be tiled as they are; running again won't help
+    #[error(transparent)]
+    Invalid(Cause),
+    /// an input image couldn't be decoded, or is beyond the decode limits
+    #[error(transparent)]
+    Decode(Cause),
+    /// a tile couldn't be encoded
+    #[error(transparent)]
+    Encode(Cause),
+    /// reading or writing failed, e.g. the disk filled up; likely worth another go
+    #[error(transparent)]
+    Io(Cause),
+}
+
+/// what went wrong, with what was being done at the time; `{:#}` shows the whole chain
+pub struct Cause(anyhow::Error);
+
+impl Cause {
+    /// the error of type `E` somewhere in the chain, if there is one
+    pub fn downcast_ref<E: Error + 'static>(&self) -> Option<&E> {
+        self.0.chain().find_map(|e| e.downcast_ref())
+    }
+}
+
+impl<E: Error + Send + Sync + 'static> From<E> for Cause {
+    fn from(e: E) -> Cause {
+        Cause(e.into())
+    }
+}
+
+impl Deref for Cause {
+    type Target = dyn Error + Send + Sync + 'static;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.deref()
+    }
+}
+
+impl fmt::Debug for Cause {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.0, f)
+    }
+}
+
+impl fmt::Display for Cause {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl TileError {
+    /// `e`, from decoding an input: the image crate's complaints, bar io, are about the image
+    pub(crate) fn decoding(e: anyhow::Error) -> TileError {
+        classify(e, TileError::Decode)
+    }
+
+    /// `e`, from encoding a tile: the image crate's complaints, bar io, are about the encoding,
+    /// e.g. a format it can't write here
+    pub(crate) fn encoding(e: anyhow::Error) -> TileError {
+        classify(e, TileError::Encode)
+    }
+}
+
+impl From<anyhow::Error> for TileError {
+    /// the image crate's complaints are taken as being about reading images, e.g. their sizes
+    /// or the shrunk copies, as decoding and encoding are sorted where they happen
+    fn from(e: anyhow::Error) -> TileError {
+        classify(e, TileError::Decode)
+    }
+}
+
+impl From<io::Error> for TileError {
+    fn from(e: io::Error) -> TileError {
+        TileError::Io(e.into())
+    }
+}
+
+/// sorted by the outermost error which says what kind of failure it was, with the image
+/// crate's non-io errors counting as `image`; anything else is a complaint about the options
+/// or inputs
+fn classify(e: anyhow::Error, image: fn(Cause) -> TileError) -> TileError {
+    let kind = e
+        .chain()
+        .find_map(|e| kind_of(e, image))
+        .unwrap_or(TileError::Invalid);
+    kind(Cause(e))
+}
+
+fn kind_of(
+    e: &(dyn Error + 'static),
+    image: fn(Cause) -> TileError,
+) -> Option<fn(Cause) -> TileError> {
+    if let Some(e) = e.downcast_ref::<TileError>() {
+        return Some(match e {
+            TileError::Invalid(_) => TileError::Invalid,
+            TileError::Decode(_) => TileError::Decode,
+            TileError::Encode(_) => TileError::Encode,
+            TileError::Io(_) => TileError::Io,
+        });
+    }
+    if let Some(e) = e.downcast_ref::<ImageError>() {
+        return Some(match e {
+            ImageError::IoError(_) => TileError::Io,
+            _ => image,
+        });
+    }
+    if e.is::<io::Error>() || e.is::<rusqlite::Error>() {
+        return Some(TileError::Io);
+    }
+    None
+}
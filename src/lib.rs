@@ -0,0 +1,3694 @@
+mod bleed;
+mod color_index;
+mod dedup;
+mod error;
+mod manifest;
+mod mbtiles;
+mod queue;
+mod semaphore;
+mod verify;
+
+use anyhow::{anyhow, Context, Result};
+use std::collections::hash_map::{DefaultHasher, Entry};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufReader, Write};
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use clap::ValueEnum;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use image::codecs::avif::{AvifEncoder, ColorSpace};
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::codecs::webp::{WebPEncoder, WebPQuality};
+use image::imageops::FilterType;
+use image::io::{Limits, Reader};
+use image::ImageEncoder;
+use image::{ColorType, DynamicImage, ImageBuffer, ImageFormat, RgbImage, Rgba, RgbaImage};
+use indicatif::{ProgressBar, ProgressStyle};
+use itertools::Itertools;
+use log::{debug, info, warn};
+use rand::prelude::*;
+use rayon::prelude::*;
+use regex::Regex;
+
+use crate::bleed::Bled;
+use crate::color_index::ColorIndex;
+use crate::dedup::Dedup;
+use crate::manifest::Manifest;
+use crate::mbtiles::MbTiles;
+use crate::queue::Queue;
+use crate::semaphore::Semaphore;
+
+pub use crate::error::{Cause, TileError};
+pub use crate::verify::verify_tiles;
+
+/// how tiles are numbered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Scheme {
+    /// y increases downwards, as used by most web maps
+    Xyz,
+    /// y increases upwards
+    Tms,
+}
+
+/// how each tile's file is named
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Naming {
+    /// by the layout, from the zoom, x and y
+    Xyz,
+    /// a single file per tile named by its quadkey, as bing maps does; there's no name for
+    /// zoom 0, so that's never written
+    Quadkey,
+}
+
+/// the bing maps name for a tile: a digit per zoom level, from the shallowest, saying which
+/// quarter of the tile above it is, with 1 for right and 2 for bottom; e.g. 3/3/5 is `213`
+fn quadkey(zoom: u32, x: u32, y: u32) -> String {
+    (0..zoom)
+        .rev()
+        .map(|bit| {
+            let right = (x >> bit) & 1;
+            let bottom = (y >> bit) & 1;
+            char::from(b'0' + (right + 2 * bottom) as u8)
+        })
+        .collect()
+}
+
+/// filter used when scaling images down
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ResizeFilter {
+    Nearest,
+    Triangle,
+    #[value(name = "catmullrom")]
+    CatmullRom,
+    Gaussian,
+    Lanczos3,
+}
+
+impl ResizeFilter {
+    pub fn filter_type(self) -> FilterType {
+        match self {
+            ResizeFilter::Nearest => FilterType::Nearest,
+            ResizeFilter::Triangle => FilterType::Triangle,
+            ResizeFilter::CatmullRom => FilterType::CatmullRom,
+            ResizeFilter::Gaussian => FilterType::Gaussian,
+            ResizeFilter::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
+/// image format tiles are written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ValueEnum)]
+pub enum OutputFormat {
+    /// colour is never subsampled (4:4:4), so thin coloured lines stay sharp; a few hardware
+    /// decoders only handle 4:2:0
+    Avif,
+    Png,
+    Webp,
+    /// no transparency; partially transparent tiles are drawn over the background colour
+    #[value(alias = "jpg")]
+    Jpeg,
+}
+
+impl OutputFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Avif => "avif",
+            OutputFormat::Png => "png",
+            OutputFormat::Webp => "webp",
+            OutputFormat::Jpeg => "jpg",
+        }
+    }
+}
+
+/// a compressed copy written beside each tile, and the metadata, for servers which can send
+/// those as they are instead of compressing every response
+///
+/// avif, webp and jpeg are already compressed, so their copies are barely smaller; it's png
+/// tiles, the tilejson and the viewer which gain
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Precompress {
+    None,
+    /// a `.gz` beside each file
+    Gzip,
+    /// a `.br` beside each file; smaller, but slower to make
+    Brotli,
+}
+
+impl Precompress {
+    /// the suffix and contents of the copy of `data`, or `None` if there isn't one
+    fn compress(self, data: &[u8]) -> Result<Option<(&'static str, Vec<u8>)>> {
+        Ok(match self {
+            Precompress::None => None,
+            Precompress::Gzip => {
+                let mut out = GzEncoder::new(Vec::new(), Compression::best());
+                out.write_all(data)?;
+                Some(("gz", out.finish()?))
+            }
+            Precompress::Brotli => {
+                // the slowest, smallest setting; it's done once, and served many times
+                let mut out = brotli::CompressorWriter::new(Vec::new(), 4096, 11, 22);
+                out.write_all(data)?;
+                Some(("br", out.into_inner()))
+            }
+        })
+    }
+}
+
+/// `path` with `.suffix` added after its extension, e.g. `1.png` to `1.png.gz`
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// inclusive range of base coordinates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bounds {
+    pub lx: i64,
+    pub ly: i64,
+    pub rx: i64,
+    pub ry: i64,
+}
+
+impl FromStr for Bounds {
+    type Err = TileError;
+
+    /// parses `LX,LY,RX,RY`
+    fn from_str(s: &str) -> Result<Bounds, TileError> {
+        let parts = s
+            .split(',')
+            .map(|v| v.trim().parse::<i64>())
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| anyhow!("parsing bounds {s:?}"))?;
+        let [lx, ly, rx, ry] = parts[..] else {
+            return Err(anyhow!("expected four comma-separated numbers, not {s:?}").into());
+        };
+        if lx > rx || ly > ry {
+            return Err(anyhow!("bounds must be ordered low to high, not {s:?}").into());
+        }
+        Ok(Bounds { lx, ly, rx, ry })
+    }
+}
+
+impl Bounds {
+    pub fn contains(&self, x: i64, y: i64) -> bool {
+        (self.lx..=self.rx).contains(&x) && (self.ly..=self.ry).contains(&y)
+    }
+}
+
+/// base coordinates which become 0,0
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Origin {
+    pub x: i64,
+    pub y: i64,
+}
+
+impl FromStr for Origin {
+    type Err = TileError;
+
+    /// parses `X,Y`
+    fn from_str(s: &str) -> Result<Origin, TileError> {
+        let parts = s
+            .split(',')
+            .map(|v| v.trim().parse::<i64>())
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| anyhow!("parsing origin {s:?}"))?;
+        let [x, y] = parts[..] else {
+            return Err(anyhow!("expected two comma-separated numbers, not {s:?}").into());
+        };
+        Ok(Origin { x, y })
+    }
+}
+
+/// a tile in a bigger pyramid, at some zoom
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TileOffset {
+    pub x: u32,
+    pub y: u32,
+}
+
+impl FromStr for TileOffset {
+    type Err = TileError;
+
+    /// parses `X,Y`
+    fn from_str(s: &str) -> Result<TileOffset, TileError> {
+        let parts = s
+            .split(',')
+            .map(|v| v.trim().parse::<u32>())
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| anyhow!("parsing tile offset {s:?}"))?;
+        let [x, y] = parts[..] else {
+            return Err(anyhow!("expected two comma-separated numbers, not {s:?}").into());
+        };
+        Ok(TileOffset { x, y })
+    }
+}
+
+/// relative path of each tile, without the extension, with `{z}`, `{x}` and `{y}` in it once each
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathTemplate(String);
+
+impl Default for PathTemplate {
+    fn default() -> PathTemplate {
+        PathTemplate("{z}/{x}/{y}".to_string())
+    }
+}
+
+impl FromStr for PathTemplate {
+    type Err = TileError;
+
+    /// parses e.g. `{z}/{y}/{x}`
+    fn from_str(s: &str) -> Result<PathTemplate, TileError> {
+        for part in ["{z}", "{x}", "{y}"] {
+            let count = s.matches(part).count();
+            if count != 1 {
+                return Err(anyhow!(
+                    "layout needs {part} exactly once, not {count} times, in {s:?}"
+                )
+                .into());
+            }
+        }
+        let path = Path::new(s);
+        if path.is_absolute() || path.components().any(|c| c == Component::ParentDir) {
+            return Err(anyhow!("layout must stay inside the output, not {s:?}").into());
+        }
+        Ok(PathTemplate(s.to_string()))
+    }
+}
+
+/// flip a top-down y to count up from the bottom, as tms does, or back again
+fn tms_y(zoom: u32, y: u32) -> u32 {
+    (2u32.pow(zoom) - 1) - y
+}
+
+/// fail if `x`, `y` isn't one of the tiles at `zoom`, which would only be a bug in the
+/// arithmetic getting there, but would otherwise be written somewhere no viewer looks
+fn check_in_level(zoom: u32, x: u32, y: u32) -> Result<()> {
+    if zoom >= 32 || x >> zoom != 0 || y >> zoom != 0 {
+        return Err(anyhow!(
+            "bug: tile {x}x{y} is outside zoom level {zoom}, which is {0}x{0} tiles",
+            1u64 << zoom.min(63)
+        ));
+    }
+    Ok(())
+}
+
+/// where each tile is written on disk
+#[derive(Debug, Clone)]
+struct TileLayout {
+    root: PathBuf,
+    scheme: Scheme,
+    template: PathTemplate,
+    naming: Naming,
+    ext: &'static str,
+    zoom_offset: u32,
+    tile_offset: TileOffset,
+}
+
+impl TileLayout {
+    fn new(root: &Path, opts: &TileOptions) -> TileLayout {
+        TileLayout {
+            root: root.to_path_buf(),
+            scheme: opts.scheme,
+            template: opts.layout.clone(),
+            naming: opts.naming,
+            ext: opts.img_ops.format.extension(),
+            zoom_offset: opts.zoom_offset,
+            tile_offset: opts.tile_offset,
+        }
+    }
+
+    /// the numbers in a tile's name, placed and in the scheme
+    fn named(&self, zoom: u32, x: u32, y: u32) -> (u32, u32, u32) {
+        let (zoom, x, y) = self.placed(zoom, x, y);
+        match self.scheme {
+            Scheme::Xyz => (zoom, x, y),
+            Scheme::Tms => (zoom, x, tms_y(zoom, y)),
+        }
+    }
+
+    /// where a tile of this run's pyramid goes in the bigger one, with the offsets; every
+    /// other method takes this run's coordinates, and does this itself
+    fn placed(&self, zoom: u32, x: u32, y: u32) -> (u32, u32, u32) {
+        let TileOffset { x: ox, y: oy } = self.tile_offset;
+        (self.zoom_offset + zoom, (ox << zoom) + x, (oy << zoom) + y)
+    }
+
+    /// `x` and `y` are always top-down, regardless of the scheme
+    fn path(&self, zoom: u32, x: u32, y: u32) -> PathBuf {
+        self.path_as(zoom, x, y, "")
+    }
+
+    /// [`TileLayout::path`], with `suffix` before the extension, e.g. `@2x`
+    fn path_as(&self, zoom: u32, x: u32, y: u32, suffix: &str) -> PathBuf {
+        let (zoom, x, y) = self.named(zoom, x, y);
+        if self.naming == Naming::Quadkey {
+            return self
+                .root
+                .join(format!("{}{suffix}.{}", quadkey(zoom, x, y), self.ext));
+        }
+        let relative = self
+            .template
+            .0
+            .replace("{z}", &zoom.to_string())
+            .replace("{x}", &x.to_string())
+            .replace("{y}", &y.to_string());
+        self.root.join(format!("{relative}{suffix}.{}", self.ext))
+    }
+
+    /// the directory holding a directory of tiles for each zoom, named as the zoom, if the
+    /// template is laid out like that; e.g. the root, for `{z}/{x}/{y}`
+    fn zoom_parent(&self) -> Option<PathBuf> {
+        if self.naming == Naming::Quadkey {
+            return None;
+        }
+        let (before, after) = self.template.0.split_once("{z}")?;
+        let whole_name = before.is_empty() || before.ends_with('/');
+        if !whole_name || !after.starts_with('/') || before.contains('{') {
+            return None;
+        }
+        Some(self.root.join(before))
+    }
+
+    /// url template for viewers, relative to the root; `suffix` goes before the extension
+    fn template(&self, suffix: &str) -> String {
+        let template = match self.naming {
+            Naming::Xyz => &self.template.0,
+            Naming::Quadkey => "{quadkey}",
+        };
+        format!("{template}{suffix}.{}", self.ext)
+    }
+}
+
+/// format input images are decoded as, whatever their names
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum InputFormat {
+    Png,
+    #[value(alias = "jpg")]
+    Jpeg,
+    /// pbm, pgm, ppm and pam
+    Pnm,
+    Bmp,
+}
+
+impl InputFormat {
+    fn image_format(self) -> ImageFormat {
+        match self {
+            InputFormat::Png => ImageFormat::Png,
+            InputFormat::Jpeg => ImageFormat::Jpeg,
+            InputFormat::Pnm => ImageFormat::Pnm,
+            InputFormat::Bmp => ImageFormat::Bmp,
+        }
+    }
+}
+
+/// reads an input image, for formats the image crate doesn't do
+pub type Decoder = fn(&Path) -> Result<DynamicImage, TileError>;
+
+/// something happening in `tile_directory`, for a program watching rather than a person
+#[derive(Debug, Clone, Copy)]
+pub enum Event<'a> {
+    /// a phase of the run is starting: "discover", "preview", "detail" or "overview"
+    PhaseStart(&'static str),
+    /// that phase is done, having taken this long
+    PhaseEnd(&'static str, Duration),
+    /// discovery found about this many tiles to write: every tile of the bases not already
+    /// done, and the whole overview
+    Planned { tiles: u64 },
+    /// the detailed tiles for a base are written, after this long manipulating and saving them
+    Base {
+        path: &'a Path,
+        x: i64,
+        y: i64,
+        manip: Duration,
+        save: Duration,
+    },
+}
+
+/// told about each `Event`, on whichever thread it happens
+#[derive(Clone)]
+pub struct EventSink(Arc<dyn Fn(&Event) + Send + Sync>);
+
+impl EventSink {
+    pub fn new(sink: impl Fn(&Event) + Send + Sync + 'static) -> EventSink {
+        EventSink(Arc::new(sink))
+    }
+}
+
+impl fmt::Debug for EventSink {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("EventSink")
+    }
+}
+
+/// colour space avif tiles are encoded in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ValueEnum)]
+pub enum AvifColorSpace {
+    /// ycbcr, which compresses better
+    Bt709,
+    /// rgb, which avoids the conversion but is larger
+    Srgb,
+}
+
+impl AvifColorSpace {
+    fn to_image(self) -> ColorSpace {
+        match self {
+            AvifColorSpace::Bt709 => ColorSpace::Bt709,
+            AvifColorSpace::Srgb => ColorSpace::Srgb,
+        }
+    }
+}
+
+/// how each tile is encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ImageOps {
+    /// 0 (terrible) - 100 (uselessly huge); not png
+    pub quality: u8,
+    /// 0 (slowest) - 10 (fastest); avif only
+    pub speed: u8,
+    pub format: OutputFormat,
+    /// webp only; ignores quality
+    pub lossless: bool,
+    /// avif only; the encoder is always fed 8-bit rgba, whatever the input depth
+    pub avif_colorspace: AvifColorSpace,
+    /// rgb drawn behind any transparency, for jpeg or when flattening
+    pub background: [u8; 3],
+    /// draw every format over the background, as jpeg always is, dropping the alpha channel
+    pub flatten: bool,
+    /// let images deeper than 8 bits a channel be reduced for formats which can't hold them,
+    /// instead of failing; only png, unflattened, keeps 16 bits
+    pub force_8bit: bool,
+    /// threads each avif encode may use, on top of the ones tiling in parallel; 0 lets it use
+    /// the whole pool. doesn't change the tiles
+    pub encode_threads: usize,
+}
+
+impl Default for ImageOps {
+    fn default() -> ImageOps {
+        ImageOps {
+            quality: 70,
+            speed: 8,
+            format: OutputFormat::Avif,
+            lossless: false,
+            avif_colorspace: AvifColorSpace::Bt709,
+            background: [255, 255, 255],
+            flatten: false,
+            force_8bit: false,
+            // tiles are already encoded in parallel, so more would just fight over the cores
+            encode_threads: 1,
+        }
+    }
+}
+
+/// what to do when two input files have the same coordinates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OnDuplicate {
+    /// refuse to continue, naming both files
+    Error,
+    /// keep the file whose path sorts first
+    First,
+    /// keep the file whose path sorts last
+    Last,
+    /// keep the most recently modified file
+    Newest,
+}
+
+/// half of the work of tiling a directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// the zoom levels covering more than one base, composited from shrunk copies of them
+    Overview,
+    /// the zoom levels cut straight out of each base
+    Detail,
+}
+
+/// everything controlling how a directory is turned into tiles
+#[derive(Debug, Clone)]
+pub struct TileOptions {
+    pub img_ops: ImageOps,
+    /// filter used when scaling images down
+    pub filter: ResizeFilter,
+    /// matches input file names; groups named `x` and `y`, or else the first two capture
+    /// groups, are the coordinates
+    ///
+    /// with a group named `z`, the inputs are already tiles at that zoom, all the same one,
+    /// numbered from the top left of the level, rather than bases to cut up; `base_size` is
+    /// then ignored, and there can't be an origin or bounds. just the shallower levels are
+    /// made from them, plus the inputs' own level again unless `max_zoom` leaves it out
+    pub name_pattern: Regex,
+    pub scheme: Scheme,
+    /// where, under the output, each tile goes
+    pub layout: PathTemplate,
+    /// with `Quadkey`, `layout` is ignored, and `scheme` must be `Xyz`; only for files
+    pub naming: Naming,
+    /// subtracted from every discovered file's coordinates, before anything else
+    pub origin: Origin,
+    /// relative to the origin; defaults to the smallest range covering every discovered file
+    pub bounds: Option<Bounds>,
+    /// width and height of each input image, in pixels
+    pub base_size: u32,
+    /// width and height of each output tile; the base size must be a power-of-two multiple of this
+    pub tile_size: u32,
+    /// regenerate tiles even if they already exist
+    pub force: bool,
+    /// draw progress bars on stderr, if it's a terminal
+    pub progress: bool,
+    /// do everything except encoding and writing tiles; the inputs are still all decoded
+    pub dry_run: bool,
+    /// write a transparent tile for every tile inside the bounds which would otherwise be missing
+    pub fill_blank: bool,
+    /// shallowest zoom level to write; defaults to 0, a single tile covering everything
+    pub min_zoom: Option<u32>,
+    /// deepest zoom level to write; defaults to where one tile pixel is one base pixel
+    pub max_zoom: Option<u32>,
+    pub on_duplicate: OnDuplicate,
+    /// skip, with a warning, images which aren't `base_size` square, instead of failing
+    pub allow_mismatched: bool,
+    /// quality for each zoom level, starting at 0, overriding the one in `img_ops`; the last
+    /// entry covers every deeper level, and an empty list uses `img_ops` everywhere
+    pub quality_by_zoom: Vec<u8>,
+    /// log input images which can't be read, and carry on without them, instead of failing
+    pub skip_errors: bool,
+    /// also write an `index.html` showing the tiles, for serving alongside them
+    pub viewer: bool,
+    /// decode inputs as this, instead of guessing from their content and name
+    pub input_format: Option<InputFormat>,
+    /// decode inputs with this instead of the image crate; `input_format` is then ignored
+    pub decoder: Option<Decoder>,
+    /// hard link tiles identical to one already written this run, instead of writing them again
+    pub dedup: bool,
+    /// scale in linear light rather than on the srgb values; more correct, but slower
+    pub linear_resize: bool,
+    /// times to retry writing a tile after an io error which might be transient
+    pub write_retries: u32,
+    /// only tile this many input images, picked at random, as if the rest were missing
+    pub sample: Option<usize>,
+    /// write the shrunk copy of each base, and each overview tile before it's scaled down, as
+    /// png under here; named by position in the grid, which starts at the bounds' top left
+    pub dump_intermediate: Option<PathBuf>,
+    /// fail, instead of warning, if any coordinate inside the bounds has no input file
+    pub strict: bool,
+    /// only write the zoom levels from this phase, within `min_zoom` and `max_zoom`; every base
+    /// is still decoded either way
+    pub only: Option<Phase>,
+    /// most bases to hold decoded at once, however many threads there are; each is
+    /// `base_size` squared times four bytes, or more for deep images. with `bleed`, a
+    /// neighbour's decoded beside each base, so each counts twice
+    pub max_in_flight: Option<usize>,
+    /// write the tiles into this mbtiles database, rather than as files under the output; the
+    /// output then only gets the intermediate dumps, if asked for
+    pub mbtiles: Option<PathBuf>,
+    /// called as the run progresses, alongside the logging
+    pub events: Option<EventSink>,
+    /// only write the detailed tiles of bases in here, and the overview tiles covering them,
+    /// leaving everything else alone; same coordinates as `bounds`
+    ///
+    /// bases outside still go into the overview, from the shrunk copies every run leaves
+    /// beside the manifest, or by decoding them again if those are missing or out of date
+    pub region: Option<Bounds>,
+    /// width of the shrunk copy of each base the overview is built from; bigger is sharper,
+    /// up to the tile size, but all of them are held in memory at once
+    pub overview_res: u32,
+    /// once this is set, stop starting new tiles, and return what's done so far; the detailed
+    /// tiles are already counted as done in the manifest, so a rerun carries on from there
+    pub interrupt: Option<Arc<AtomicBool>>,
+    /// as if `interrupt` was set at this moment, e.g. to fit a run into a time limit; a later
+    /// run without one finishes the job
+    pub deadline: Option<Instant>,
+    /// take the input files, and their coordinates, from this list of `name,x,y` lines,
+    /// instead of finding them with `name_pattern`
+    pub coordinates: Option<PathBuf>,
+    /// scale each detailed tile with this many of its output pixels of context around it,
+    /// taken from the neighbouring bases at a base's edge, so the edges match up; this
+    /// decodes each base's neighbours too
+    pub bleed: u32,
+    /// before decoding anything, write a rough version of the shallowest few overview levels
+    /// from the shrunk copies left by earlier runs, or grey squares where there are none, so
+    /// there's a map to look at straight away; the real overview replaces it at the end
+    pub fast_overview: bool,
+    /// speed for the overview tiles, instead of the one in `img_ops`; avif only
+    pub overview_speed: Option<u8>,
+    /// speed for the detailed tiles, instead of the one in `img_ops`; avif only
+    pub detail_speed: Option<u8>,
+    /// filter for shrinking the bases and compositing the overview tiles, instead of `filter`
+    pub overview_filter: Option<ResizeFilter>,
+    /// filter for the detailed tiles, instead of `filter`
+    pub detail_filter: Option<ResizeFilter>,
+    /// also write a compressed copy of each tile, the tilejson and the viewer beside them;
+    /// not into an mbtiles database
+    pub precompress: Precompress,
+    /// only files with one of these extensions, compared ignoring case, are taken as inputs
+    /// by `name_pattern`, so a stray `foo_3_4.txt` is skipped rather than failing to decode;
+    /// `*` takes anything
+    pub extensions: Vec<String>,
+    /// tiles whose every pixel has an alpha up to this, out of 255, are skipped as transparent,
+    /// so faint anti-aliasing halos don't keep empty tiles; anything drawn that faintly is lost
+    pub transparent_threshold: u8,
+    /// once every base has been looked at, write a png here with a pixel per base in the
+    /// bounds: green where there's something, grey where it's transparent, red where it's missing
+    pub coverage_map: Option<PathBuf>,
+    /// more directories, or single files, to find inputs in, as well as the one passed in;
+    /// can't be used with `coordinates`
+    pub more_inputs: Vec<PathBuf>,
+    /// keep an existing detailed tile if it was written after its base last changed, and
+    /// remake it if not, whatever the manifest says; e.g. for an output whose manifest is
+    /// lost, or from an older version. tiles in an mbtiles database have no times to compare
+    pub overwrite_newer: bool,
+    /// take inputs smaller than `base_size`, e.g. partial bases at the edge of a capture, by
+    /// putting them in the top left of a transparent base; larger ones are still wrong
+    pub pad_partial: bool,
+    /// make the whole pyramid in a fresh directory beside `output`, then swap it into place
+    /// once it's finished, so nothing reading `output` ever sees half of one; nothing from
+    /// earlier runs is reused, and an interrupted run leaves `output` untouched
+    pub staging: bool,
+    /// also write each tile at twice the size, beside it as `{y}@2x`, for high dpi screens;
+    /// the deepest level's are just scaled up. not into an mbtiles database or a sink
+    pub retina: bool,
+    /// remove the tiles of any zoom level deeper than `max_zoom` left by earlier runs, and
+    /// then their directories if that empties them; just logged on a dry run. the layout
+    /// must have a directory for each zoom, and there's no pruning an mbtiles database
+    pub prune_zooms: bool,
+    /// most the built-in decoders may allocate for one image, which then fails to decode
+    /// rather than exhausting memory; defaults to the image crate's 512MB. some decoders
+    /// only roughly keep to it
+    pub max_decode_bytes: Option<u64>,
+    /// widest, and tallest, image the built-in decoders will decode
+    pub max_decode_dimensions: Option<u32>,
+    /// added to every zoom level written, so this run's pyramid can be part of a bigger one;
+    /// `min_zoom`, `max_zoom` and the stats are still numbered from this run's zoom 0
+    pub zoom_offset: u32,
+    /// where this run's zoom 0 tile goes at `zoom_offset` in the bigger pyramid, which every
+    /// deeper tile follows; must be inside that level. ignored by [`tile_base`]
+    pub tile_offset: TileOffset,
+    /// keep a json file here of the mean colour of every tile, keyed by `z/x/y` as in the
+    /// tile's name, for viewers to show while tiles load; updated, not replaced, by each run.
+    /// not written on a dry run
+    pub color_index: Option<PathBuf>,
+    /// skip tiles, and bases, with nothing drawn in them, as judged by `transparent_threshold`;
+    /// turning this off encodes every tile there's an input under, to rule the check out when
+    /// something seems to be missing
+    pub skip_transparent: bool,
+    /// before tiling, estimate the space the tiles will take, from encoding a tile from each
+    /// of a few bases, and fail if the output's filesystem doesn't have that free; bases whose
+    /// tile at the one-tile-per-base zoom is already there are taken as done
+    pub space_check: bool,
+}
+
+impl Default for TileOptions {
+    fn default() -> TileOptions {
+        TileOptions {
+            img_ops: ImageOps::default(),
+            filter: ResizeFilter::Lanczos3,
+            name_pattern: Regex::new(DEFAULT_NAME_PATTERN).expect("static regex"),
+            scheme: Scheme::Xyz,
+            layout: PathTemplate::default(),
+            naming: Naming::Xyz,
+            origin: Origin::default(),
+            bounds: None,
+            base_size: 4096,
+            tile_size: 256,
+            force: false,
+            progress: false,
+            dry_run: false,
+            fill_blank: false,
+            min_zoom: None,
+            max_zoom: None,
+            on_duplicate: OnDuplicate::Error,
+            allow_mismatched: false,
+            quality_by_zoom: Vec::new(),
+            skip_errors: false,
+            viewer: false,
+            input_format: None,
+            decoder: None,
+            dedup: false,
+            linear_resize: false,
+            write_retries: 3,
+            sample: None,
+            dump_intermediate: None,
+            strict: false,
+            only: None,
+            max_in_flight: None,
+            mbtiles: None,
+            events: None,
+            region: None,
+            overview_res: 256,
+            interrupt: None,
+            deadline: None,
+            coordinates: None,
+            bleed: 0,
+            fast_overview: false,
+            overview_speed: None,
+            detail_speed: None,
+            overview_filter: None,
+            detail_filter: None,
+            precompress: Precompress::None,
+            extensions: DEFAULT_EXTENSIONS.split(',').map(str::to_string).collect(),
+            transparent_threshold: 0,
+            coverage_map: None,
+            more_inputs: Vec::new(),
+            overwrite_newer: false,
+            pad_partial: false,
+            staging: false,
+            retina: false,
+            prune_zooms: false,
+            max_decode_bytes: None,
+            max_decode_dimensions: None,
+            zoom_offset: 0,
+            tile_offset: TileOffset::default(),
+            color_index: None,
+            skip_transparent: true,
+            space_check: true,
+        }
+    }
+}
+
+impl TileOptions {
+    /// the encoding settings for tiles at `zoom`, in `phase`, with the quality from
+    /// `quality_by_zoom` and the phase's speed
+    pub fn img_ops_at(&self, zoom: u32, phase: Phase) -> ImageOps {
+        let speed = match phase {
+            Phase::Overview => self.overview_speed,
+            Phase::Detail => self.detail_speed,
+        };
+        ImageOps {
+            quality: self
+                .quality_by_zoom
+                .get(zoom as usize)
+                .or(self.quality_by_zoom.last())
+                .copied()
+                .unwrap_or(self.img_ops.quality),
+            speed: speed.unwrap_or(self.img_ops.speed),
+            ..self.img_ops
+        }
+    }
+
+    /// the filter for scaling down in `phase`
+    pub fn filter_at(&self, phase: Phase) -> ResizeFilter {
+        match phase {
+            Phase::Overview => self.overview_filter,
+            Phase::Detail => self.detail_filter,
+        }
+        .unwrap_or(self.filter)
+    }
+
+    /// decode an input image, with the custom decoder if there is one
+    pub fn decode(&self, path: &Path) -> Result<DynamicImage, TileError> {
+        let img = match self.decoder {
+            Some(decoder) => decoder(path).map_err(Into::into),
+            None => self.reader(path).and_then(|reader| Ok(reader.decode()?)),
+        }
+        .with_context(|| anyhow!("decoding {path:?}"))
+        .map_err(TileError::decoding)?;
+        let img = working_space(&img).unwrap_or(img);
+        Ok(self.padded(&img).unwrap_or(img))
+    }
+
+    /// `img` in the top left of a transparent base, with `pad_partial`, if it's smaller than
+    /// one; else `None`, leaving the size to be checked
+    fn padded(&self, img: &DynamicImage) -> Option<DynamicImage> {
+        let wh = self.base_size;
+        let (w, h) = (img.width(), img.height());
+        if !self.pad_partial || (w, h) == (wh, wh) || w > wh || h > wh {
+            return None;
+        }
+        let color = img.color();
+        Some(if color.bytes_per_pixel() > color.channel_count() {
+            let mut padded = ImageBuffer::new(wh, wh);
+            image::imageops::replace(&mut padded, &img.to_rgba16(), 0, 0);
+            DynamicImage::ImageRgba16(padded)
+        } else {
+            let mut padded = RgbaImage::new(wh, wh);
+            image::imageops::replace(&mut padded, &img.to_rgba8(), 0, 0);
+            DynamicImage::ImageRgba8(padded)
+        })
+    }
+
+    /// a reader for the built-in decoders, in `input_format` or whatever the file looks like
+    fn reader(&self, path: &Path) -> Result<Reader<BufReader<File>>> {
+        let mut reader = Reader::open(path).with_context(|| anyhow!("opening {path:?}"))?;
+        match self.input_format {
+            Some(format) => reader.set_format(format.image_format()),
+            // sniffs the content, falling back to the extension
+            None => {
+                reader = reader
+                    .with_guessed_format()
+                    .with_context(|| anyhow!("reading {path:?}"))?
+            }
+        }
+        if reader.format().is_none() {
+            return Err(anyhow!(
+                "can't tell what format {path:?} is in; see --input-format"
+            ));
+        }
+        reader.limits(self.limits());
+        Ok(reader)
+    }
+
+    /// decode an image this wrote itself, e.g. a shrunk copy, in whatever format it's in
+    fn open_own(&self, path: &Path) -> Result<DynamicImage> {
+        let mut reader = Reader::open(path)?.with_guessed_format()?;
+        reader.limits(self.limits());
+        Ok(reader.decode()?)
+    }
+
+    fn limits(&self) -> Limits {
+        let mut limits = Limits::default();
+        if let Some(bytes) = self.max_decode_bytes {
+            limits.max_alloc = Some(bytes);
+        }
+        limits.max_image_width = self.max_decode_dimensions;
+        limits.max_image_height = self.max_decode_dimensions;
+        limits
+    }
+
+    /// whether the inputs are tiles already, named with their zoom
+    fn pretiled(&self) -> bool {
+        self.coordinates.is_none()
+            && self
+                .name_pattern
+                .capture_names()
+                .flatten()
+                .any(|name| name == "z")
+    }
+
+    /// tiles across each base at the deepest zoom, after checking the sizes are compatible
+    fn tiles_per_base(&self) -> Result<u32> {
+        let base_wh = self.base_size;
+        let tile_wh = self.tile_size;
+        if !base_wh.is_multiple_of(tile_wh) {
+            return Err(anyhow!(
+                "base size ({base_wh}) must be a multiple of tile size ({tile_wh})"
+            ));
+        }
+        let tile_per_base = base_wh / tile_wh;
+        if !tile_per_base.is_power_of_two() {
+            return Err(anyhow!(
+                "base size ({base_wh}) must be a power-of-two multiple of tile size ({tile_wh})"
+            ));
+        }
+        Ok(tile_per_base)
+    }
+}
+
+/// how many of the shallowest zoom levels `fast_overview` writes
+const FAST_OVERVIEW_ZOOMS: u32 = 3;
+
+/// after the name of each retina tile, before its extension, as viewers expect
+const RETINA_SUFFIX: &str = "@2x";
+
+/// deepest pre-tiled inputs can be at, as the whole level is the grid of bases
+const MAX_PRETILED_ZOOM: u32 = 12;
+
+/// bases to encode a tile from, to estimate the size of the rest
+const SPACE_SAMPLES: usize = 3;
+
+/// most memory to spend holding the shrunk copies of the bases, at 8 bits per channel
+const MAX_SHRUNK_BYTES: u64 = 16 << 30;
+
+/// matches names like `foo_3_-4.png`
+pub const DEFAULT_NAME_PATTERN: &str = r".*_(-?\d+)_(-?\d+)\.";
+
+/// everything the built-in decoders read
+pub const DEFAULT_EXTENSIONS: &str = "png,jpg,jpeg,bmp,pbm,pgm,ppm,pam,pnm,webp";
+
+/// what happened during a [`tile_directory`] run
+#[derive(Debug, Clone, Default)]
+pub struct TileStats {
+    /// input images inside the bounds
+    pub bases: usize,
+    /// input images inside the bounds which weren't entirely transparent
+    pub non_blank: usize,
+    /// tiles written (or, for a dry run, which would have been), indexed by zoom level
+    pub tiles: Vec<usize>,
+    /// total size of the tiles written, indexed by zoom level; zero for a dry run
+    pub bytes: Vec<u64>,
+    /// tiles not written as they were entirely transparent
+    pub transparent_tiles: usize,
+    /// input images skipped as they couldn't be read, with `skip_errors`
+    pub unreadable: usize,
+    /// tiles hard linked to an identical one, instead of written, with `dedup`
+    pub deduplicated: usize,
+    /// time spent cropping, compositing and resizing, indexed by zoom level; summed across
+    /// threads, so may well exceed the time the run took
+    pub manip_time: Vec<Duration>,
+    /// time spent encoding and writing tiles, indexed by zoom level; summed across threads
+    pub save_time: Vec<Duration>,
+    /// stopped early by `interrupt` or `deadline`, so the overview (and tilejson) weren't written
+    pub interrupted: bool,
+    /// input images inside the bounds which were finished with, this run or an earlier one;
+    /// all of them, unless interrupted
+    pub bases_done: usize,
+}
+
+/// every file under each of `inputs` whose name `format` matches, and which has one of
+/// `extensions`, with the coordinates it gives; matching files with other extensions are
+/// warned about. inputs which are files are taken whatever their extension, but must match
+fn discover(
+    inputs: &[&Path],
+    format: &Regex,
+    extensions: &[String],
+) -> Result<Vec<(i64, i64, PathBuf)>> {
+    let image_like = |path: &Path| {
+        extensions.iter().any(|wanted| {
+            wanted == "*"
+                || path
+                    .extension()
+                    .and_then(|ext| ext.to_str())
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case(wanted))
+        })
+    };
+    // the coordinates in the name, if it matches
+    let coordinates = |path: &Path| -> Result<Option<(i64, i64)>> {
+        let Some(file_name) = path.file_name() else {
+            return Ok(None);
+        };
+        let file_name = file_name
+            .to_str()
+            .ok_or_else(|| anyhow!("unrepresentable filename: {file_name:?}"))?;
+        let Some(captures) = format.captures(file_name) else {
+            return Ok(None);
+        };
+        let x = captures
+            .name("x")
+            .or_else(|| captures.get(1))
+            .ok_or(anyhow!("missing capture group 1"))?
+            .as_str()
+            .parse::<i64>()?;
+        let y = captures
+            .name("y")
+            .or_else(|| captures.get(2))
+            .ok_or(anyhow!("missing capture group 2"))?
+            .as_str()
+            .parse::<i64>()?;
+        Ok(Some((x, y)))
+    };
+    let mut bases = Vec::new();
+    let mut ignored = Vec::new();
+    // coordinates are global, so subdirectories are just for the user's organisation
+    let mut dirs = Vec::new();
+    for input in inputs {
+        let meta = fs::metadata(input).with_context(|| anyhow!("reading {input:?}"))?;
+        if meta.is_dir() {
+            dirs.push(input.to_path_buf());
+            continue;
+        }
+        let Some((x, y)) = coordinates(input)? else {
+            return Err(anyhow!(
+                "{input:?} was given as an input, but doesn't match the name pattern {:?}",
+                format.as_str()
+            ));
+        };
+        bases.push((x, y, input.to_path_buf()));
+    }
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir).with_context(|| anyhow!("listing {dir:?}"))? {
+            let entry = entry?;
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+            let Some((x, y)) = coordinates(&path)? else {
+                continue;
+            };
+            if !image_like(&path) {
+                ignored.push(path);
+                continue;
+            }
+            bases.push((x, y, path));
+        }
+    }
+    if !ignored.is_empty() {
+        warn!(
+            "ignoring {} files matching the name pattern without an image extension \
+             (see --extensions), e.g. {:?}",
+            ignored.len(),
+            ignored.iter().take(5).format(", ")
+        );
+    }
+    Ok(bases)
+}
+
+/// the zoom level every pre-tiled input is at, from the `z` group of its name
+fn input_zoom(bases: &[(i64, i64, PathBuf)], format: &Regex) -> Result<u32> {
+    let mut first: Option<(u32, &Path)> = None;
+    for (_, _, path) in bases {
+        let name = path.file_name().and_then(|name| name.to_str());
+        let zoom = name
+            .and_then(|name| format.captures(name)?.name("z"))
+            .ok_or_else(|| anyhow!("no zoom in the name of {path:?}"))?
+            .as_str()
+            .parse::<u32>()
+            .with_context(|| anyhow!("reading the zoom from {path:?}"))?;
+        match first {
+            None => first = Some((zoom, path)),
+            Some((expected, other)) if zoom != expected => {
+                return Err(anyhow!(
+                    "pre-tiled inputs must all be at one zoom, but {other:?} is at {expected} \
+                     and {path:?} is at {zoom}"
+                ));
+            }
+            Some(_) => (),
+        }
+    }
+    let (zoom, _) = first.ok_or_else(|| anyhow!("no pre-tiled inputs"))?;
+    if zoom > MAX_PRETILED_ZOOM {
+        return Err(anyhow!(
+            "pre-tiled inputs at zoom {zoom} are too deep; the whole level is held as a grid, \
+             so {MAX_PRETILED_ZOOM} is the deepest"
+        ));
+    }
+    Ok(zoom)
+}
+
+/// the files listed in `list`, one `name,x,y` line each, with names relative to `input`;
+/// a first line without coordinates is taken as a header, and listed files which don't
+/// exist are left out, with a warning
+fn read_coordinates(list: &Path, input: &Path) -> Result<Vec<(i64, i64, PathBuf)>> {
+    let text = fs::read_to_string(list).with_context(|| anyhow!("reading {list:?}"))?;
+    let mut bases = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        // from the right, so names can have commas in
+        let mut parts = line.rsplitn(3, ',');
+        let (Some(y), Some(x), Some(name)) = (parts.next(), parts.next(), parts.next()) else {
+            return Err(anyhow!(
+                "expected name,x,y on line {} of {list:?}, not {line:?}",
+                number + 1
+            ));
+        };
+        let (x, y) = match (x.trim().parse::<i64>(), y.trim().parse::<i64>()) {
+            (Ok(x), Ok(y)) => (x, y),
+            _ if number == 0 => continue,
+            _ => {
+                return Err(anyhow!(
+                    "bad coordinates on line {} of {list:?}: {line:?}",
+                    number + 1
+                ))
+            }
+        };
+        let path = input.join(name.trim());
+        if !path.is_file() {
+            warn!("{path:?}, listed in {list:?}, doesn't exist");
+            continue;
+        }
+        bases.push((x, y, path));
+    }
+    Ok(bases)
+}
+
+/// the options which decide what bases are found, and what's made from them, for telling
+/// whether a queue was left by a run like this one
+fn queue_key(input: &Path, opts: &TileOptions) -> String {
+    // the callbacks can't be compared between runs, and the rest don't change the tiles
+    let opts = TileOptions {
+        progress: false,
+        decoder: None,
+        events: None,
+        interrupt: None,
+        deadline: None,
+        max_in_flight: None,
+        dump_intermediate: None,
+        write_retries: 0,
+        viewer: false,
+        fast_overview: false,
+        coverage_map: None,
+        ..opts.clone()
+    };
+    format!("{input:?} {opts:?}")
+}
+
+/// turn a directory full of input images into a tile pyramid under `output`
+pub fn tile_directory(
+    input: &Path,
+    output: &Path,
+    opts: &TileOptions,
+) -> Result<TileStats, TileError> {
+    Ok(tile_directory_with(input, output, opts, None)?)
+}
+
+/// takes each tile's zoom, x, y and encoded bytes, from many threads at once; x and y are
+/// top-down, whatever the scheme
+pub type TileSink<'a> = &'a (dyn Fn(u32, u32, u32, &[u8]) -> Result<(), TileError> + Sync);
+
+/// [`tile_directory`], but handing each tile to `sink` as soon as it's encoded, instead of
+/// writing it, e.g. to upload it
+///
+/// `output` still gets the manifest, the other notes for resuming, and the tilejson. what the
+/// sink already has isn't known, so every tile is made each run, except the bases an
+/// interrupted run finished. `mbtiles`, `dedup` and `precompress` don't apply
+pub fn tile_directory_into(
+    input: &Path,
+    output: &Path,
+    opts: &TileOptions,
+    sink: impl Fn(u32, u32, u32, &[u8]) -> Result<(), TileError> + Sync,
+) -> Result<TileStats, TileError> {
+    if opts.mbtiles.is_some() {
+        return Err(anyhow!("can't write into an mbtiles database and a sink at once").into());
+    }
+    if opts.retina {
+        return Err(
+            anyhow!("a sink takes one tile at each position, so can't have retina tiles").into(),
+        );
+    }
+    Ok(tile_directory_with(input, output, opts, Some(&sink))?)
+}
+
+fn tile_directory_with(
+    input: &Path,
+    output: &Path,
+    opts: &TileOptions,
+    sink: Option<TileSink>,
+) -> Result<TileStats> {
+    // before anything's made in the output, and as a mistake, rather than an io error, which
+    // might be worth retrying
+    for input in [input]
+        .into_iter()
+        .chain(opts.more_inputs.iter().map(PathBuf::as_path))
+    {
+        if !input.exists() {
+            return Err(anyhow!("there's no {input:?} to tile"));
+        }
+    }
+    if opts.staging {
+        let name = output
+            .file_name()
+            .ok_or_else(|| anyhow!("staging needs a named output directory, not {output:?}"))?;
+        let staging = output.with_file_name(format!("{}.tiledir-staging", name.to_string_lossy()));
+        // left by an interrupted run, which can't be carried on with; a dry run leaves it be,
+        // and plans as if it were gone, by forcing every tile
+        let stale = staging.exists();
+        if stale && opts.dry_run {
+            info!("would remove {staging:?}, left by an interrupted run");
+        } else if stale {
+            fs::remove_dir_all(&staging).with_context(|| anyhow!("removing {staging:?}"))?;
+        }
+        let opts = TileOptions {
+            staging: false,
+            force: opts.force || (stale && opts.dry_run),
+            ..opts.clone()
+        };
+        let stats = tile_directory_with(input, &staging, &opts, sink)?;
+        if !stats.interrupted && !opts.dry_run {
+            info!("moving the finished pyramid into place at {output:?}");
+            replace_dir(&staging, output)?;
+        }
+        return Ok(stats);
+    }
+    // each pre-tiled input is a base a tile across
+    let pretiled;
+    let opts = if opts.pretiled() {
+        if opts.origin != Origin::default() || opts.bounds.is_some() {
+            return Err(anyhow!(
+                "pre-tiled inputs are numbered from the top left of their zoom level, \
+                 so can't have an origin or bounds"
+            ));
+        }
+        pretiled = TileOptions {
+            base_size: opts.tile_size,
+            overview_res: opts.overview_res.min(opts.tile_size),
+            ..opts.clone()
+        };
+        &pretiled
+    } else {
+        opts
+    };
+    let format = &opts.name_pattern;
+    let img_ops = opts.img_ops;
+
+    let layout = TileLayout::new(output, opts);
+    let overview_filter = opts.filter_at(Phase::Overview).filter_type();
+    let detail_filter = opts.filter_at(Phase::Detail).filter_type();
+    // not written to on a dry run, which also doesn't make a database just to look in it
+    let manifest = match &opts.mbtiles {
+        Some(path) => path.with_extension("tiledir-manifest"),
+        None => output.join(".tiledir-manifest"),
+    };
+    let manifest = Manifest::open(&manifest, !opts.dry_run)?;
+    // an unfinished run's bases, so a rerun can skip finding them, and those it finished
+    let queue = match &opts.mbtiles {
+        Some(path) => path.with_extension("tiledir-queue"),
+        None => output.join(".tiledir-queue"),
+    };
+    let shrunk_cache = match &opts.mbtiles {
+        Some(path) => path.with_extension("tiledir-shrunk"),
+        None => output.join(".tiledir-shrunk"),
+    };
+    let mbtiles = match &opts.mbtiles {
+        Some(path) if opts.dry_run && !path.exists() => None,
+        Some(path) => Some(MbTiles::open(path)?),
+        None => None,
+    };
+    let exists = |zoom: u32, x: u32, y: u32| match (&opts.mbtiles, &mbtiles) {
+        _ if sink.is_some() => false,
+        (None, _) => layout.path(zoom, x, y).exists(),
+        (Some(_), Some(mbtiles)) => {
+            let (zoom, x, y) = layout.placed(zoom, x, y);
+            mbtiles.contains(zoom, x, y)
+        }
+        (Some(_), None) => false,
+    };
+    // only files have times to compare, so a database or sink goes by the manifest regardless
+    let by_time = opts.overwrite_newer && opts.mbtiles.is_none() && sink.is_none();
+    let written_since = |zoom: u32, x: u32, y: u32, time: SystemTime| {
+        fs::metadata(layout.path(zoom, x, y))
+            .and_then(|meta| meta.modified())
+            .is_ok_and(|modified| modified >= time)
+    };
+
+    let base_wh = opts.base_size;
+    let tile_wh = opts.tile_size;
+    let tile_per_base = opts.tiles_per_base()?;
+    // zoom levels which split a single base into multiple tiles, beyond the one-tile-per-base
+    // level; e.g. 4, for 16 tiles across each base
+    let detail_zooms = tile_per_base.trailing_zeros();
+
+    let interrupted = || {
+        opts.interrupt
+            .as_ref()
+            .is_some_and(|flag| flag.load(Ordering::SeqCst))
+            || opts
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+    };
+    let event = |event: Event| {
+        if let Some(EventSink(events)) = &opts.events {
+            events(&event);
+        }
+    };
+    let phase_end = |name: &'static str, start: Instant| {
+        event(Event::PhaseEnd(name, start.elapsed()));
+    };
+
+    if opts.coordinates.is_some() && !opts.more_inputs.is_empty() {
+        return Err(anyhow!(
+            "a coordinates list names files in one directory, so can't have more inputs"
+        ));
+    }
+    let TileOffset { x: ox, y: oy } = opts.tile_offset;
+    if opts.zoom_offset >= 32 || (ox | oy) >> opts.zoom_offset != 0 {
+        return Err(anyhow!(
+            "tile offset {ox},{oy} is outside zoom level {}, which is 2^{0} tiles across",
+            opts.zoom_offset
+        ));
+    }
+    if opts.naming == Naming::Quadkey && opts.scheme == Scheme::Tms {
+        return Err(anyhow!(
+            "quadkeys always count rows downwards, so can't be used with the tms scheme"
+        ));
+    }
+    if opts.retina && opts.mbtiles.is_some() {
+        return Err(anyhow!(
+            "an mbtiles database has one tile at each position, so can't have retina tiles"
+        ));
+    }
+    // bleeding decodes each base's neighbours, one at a time, while the base is held
+    let decoded_per_base = if opts.bleed > 0 { 2 } else { 1 };
+    if let Some(most) = opts.max_in_flight.filter(|most| *most < decoded_per_base) {
+        return Err(anyhow!(
+            "max in flight ({most}) must be at least {decoded_per_base}{}",
+            if opts.bleed > 0 {
+                ", as bleeding decodes a neighbour beside each base"
+            } else {
+                ""
+            }
+        ));
+    }
+    info!("discovering files...");
+    event(Event::PhaseStart("discover"));
+    let started = Instant::now();
+    let queue = Queue::open(&queue, &queue_key(input, opts), !opts.dry_run, || {
+        let bases = match &opts.coordinates {
+            Some(list) => read_coordinates(list, input)?,
+            None => {
+                let inputs = [input]
+                    .into_iter()
+                    .chain(opts.more_inputs.iter().map(PathBuf::as_path))
+                    .collect_vec();
+                discover(&inputs, format, &opts.extensions)?
+            }
+        };
+        if bases.is_empty() {
+            return Err(match &opts.coordinates {
+                Some(list) => anyhow!("none of the files listed in {list:?} exist"),
+                None => anyhow!(
+                    "no input files in {input:?} matched the name pattern {:?}",
+                    format.as_str()
+                ),
+            });
+        }
+        Ok(bases)
+    })?;
+    let mut bases = queue.bases();
+    for (x, y, _) in &mut bases {
+        *x -= opts.origin.x;
+        *y -= opts.origin.y;
+    }
+
+    // pre-tiled inputs keep their numbers by having the grid be their whole zoom level
+    let level = match opts.pretiled() {
+        true => {
+            let edge = (1 << input_zoom(&bases, format)?) - 1;
+            Some(Bounds {
+                lx: 0,
+                ly: 0,
+                rx: edge,
+                ry: edge,
+            })
+        }
+        false => None,
+    };
+    let Bounds { lx, ly, rx, ry } = match opts.bounds.or(level) {
+        Some(bounds) => bounds,
+        None => {
+            let (lx, rx) = bases
+                .iter()
+                .map(|(x, _, _)| *x)
+                .minmax()
+                .into_option()
+                .expect("non-empty");
+            let (ly, ry) = bases
+                .iter()
+                .map(|(_, y, _)| *y)
+                .minmax()
+                .into_option()
+                .expect("non-empty");
+            Bounds { lx, ly, rx, ry }
+        }
+    };
+
+    let bw = u32::try_from(rx - lx + 1)?;
+    let bh = u32::try_from(ry - ly + 1)?;
+
+    info!("files available from {lx}x{ly} -> {rx}x{ry} ({bw}x{bh})");
+
+    // the pyramid needs a power-of-two grid of bases; anything short of that is left blank
+    let grid_wh = bw.max(bh).next_power_of_two();
+    // zoom levels which cover more than one base, and hence come from the composited image
+    let overview_zooms = grid_wh.trailing_zeros();
+
+    // past this, tiles would be upscaled from less than a tile's worth of base
+    let deepest_zoom = overview_zooms + detail_zooms;
+    // a quadkey can't name the zoom 0 tile
+    let unnamed = u32::from(opts.naming == Naming::Quadkey && opts.zoom_offset == 0);
+    let min_zoom = opts.min_zoom.unwrap_or(unnamed);
+    let max_zoom = opts.max_zoom.unwrap_or(deepest_zoom);
+    if min_zoom < unnamed {
+        return Err(anyhow!(
+            "zoom 0 has no quadkey, so can't be written with quadkey naming"
+        ));
+    }
+    // beyond 31, a level's tiles can't be counted in a u32
+    if opts.zoom_offset + deepest_zoom > 31 {
+        return Err(anyhow!(
+            "zoom offset {} puts the deepest zoom ({deepest_zoom}) at {}, past the deepest \
+             possible, 31",
+            opts.zoom_offset,
+            opts.zoom_offset + deepest_zoom
+        ));
+    }
+    if max_zoom > deepest_zoom {
+        return Err(anyhow!(
+            "max zoom ({max_zoom}) is deeper than the bases' resolution allows ({deepest_zoom}); \
+             try a larger base size or a smaller tile size"
+        ));
+    }
+    if min_zoom > max_zoom {
+        return Err(anyhow!(
+            "min zoom ({min_zoom}) must not be above max zoom ({max_zoom})"
+        ));
+    }
+    if opts.prune_zooms {
+        if opts.mbtiles.is_some() {
+            return Err(anyhow!("can't prune zoom levels from an mbtiles database"));
+        }
+        prune_zooms(&layout, max_zoom + opts.zoom_offset, opts.dry_run)?;
+    }
+    let wanted = |zoom: u32| {
+        (min_zoom..=max_zoom).contains(&zoom)
+            && match opts.only {
+                None => true,
+                Some(Phase::Overview) => zoom < overview_zooms,
+                Some(Phase::Detail) => zoom >= overview_zooms,
+            }
+    };
+    match opts.only {
+        None => info!("generating zoom levels {min_zoom} -> {max_zoom}"),
+        Some(phase) => info!("generating zoom levels {min_zoom} -> {max_zoom}, {phase:?} only"),
+    }
+
+    // directory listing order is arbitrary, so sort for first and last to mean something
+    bases.sort_unstable_by(|(_, _, a), (_, _, b)| a.cmp(b));
+    let mut base_lookup = HashMap::<_, PathBuf>::with_capacity(bases.len());
+    for (x, y, path) in bases {
+        let existing = match base_lookup.entry((x, y)) {
+            Entry::Occupied(existing) => existing.into_mut(),
+            Entry::Vacant(slot) => {
+                slot.insert(path);
+                continue;
+            }
+        };
+        let replace = match opts.on_duplicate {
+            OnDuplicate::Error => {
+                return Err(anyhow!("both {existing:?} and {path:?} are for {x}x{y}"));
+            }
+            OnDuplicate::First => false,
+            OnDuplicate::Last => true,
+            OnDuplicate::Newest => modified(&path)? > modified(existing)?,
+        };
+        if replace {
+            info!("{x}x{y}: using {path:?}, ignoring {existing:?}");
+            *existing = path;
+        } else {
+            info!("{x}x{y}: using {existing:?}, ignoring {path:?}");
+        }
+    }
+
+    if !base_lookup
+        .keys()
+        .any(|(x, y)| (lx..=rx).contains(x) && (ly..=ry).contains(y))
+    {
+        return Err(anyhow!(
+            "none of the {} input files are inside the bounds {lx},{ly},{rx},{ry}",
+            base_lookup.len()
+        ));
+    }
+
+    // counted lazily, as the bounds can be vast and mostly full
+    let gaps = || {
+        (lx..=rx)
+            .cartesian_product(ly..=ry)
+            .filter(|xy| !base_lookup.contains_key(xy))
+    };
+    let gap_count = gaps().count();
+    if gap_count > 0 {
+        let examples = gaps().take(5).map(|(x, y)| format!("{x}x{y}")).join(", ");
+        let msg =
+            format!("{gap_count} of the {bw}x{bh} grid cells have no input file, e.g. {examples}");
+        if opts.strict {
+            return Err(anyhow!("{msg}"));
+        }
+        // pre-tiled inputs rarely cover a whole level, so that's expected
+        if level.is_some() {
+            info!("{msg}");
+        } else {
+            warn!("{msg}");
+        }
+    }
+
+    // only the headers are read, so this is cheap next to the real pass; a custom decoder can't
+    // do that, so its images are only checked once decoded
+    let sizes = base_lookup
+        .par_iter()
+        .filter(|_| opts.decoder.is_none())
+        .filter(|((x, y), _)| (lx..=rx).contains(x) && (ly..=ry).contains(y))
+        .map(|(&xy, path)| {
+            let size = opts
+                .reader(path)
+                .and_then(|reader| Ok(reader.into_dimensions()?))
+                .with_context(|| anyhow!("reading the size of {path:?}"));
+            (xy, size)
+        })
+        .collect::<Vec<_>>();
+    let unreadable = AtomicUsize::new(0);
+    let mut mismatched = Vec::new();
+    for (xy, size) in sizes {
+        match size {
+            Ok((w, h)) if (w, h) == (base_wh, base_wh) => (),
+            Ok((w, h)) if opts.pad_partial && w <= base_wh && h <= base_wh => (),
+            Ok((w, h)) => mismatched.push((xy, format!("{:?} is {w}x{h}", base_lookup[&xy]))),
+            Err(e) if opts.skip_errors => {
+                warn!("skipping: {e:#}");
+                unreadable.fetch_add(1, Ordering::SeqCst);
+                base_lookup.remove(&xy);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    if !mismatched.is_empty() {
+        let list = mismatched.iter().map(|(_, msg)| msg).join(", ");
+        if !opts.allow_mismatched {
+            return Err(anyhow!(
+                "every image should be {base_wh}x{base_wh} (see --base-size), but {list}"
+            ));
+        }
+        warn!("skipping images which aren't {base_wh}x{base_wh}: {list}");
+        for (xy, _) in &mismatched {
+            base_lookup.remove(xy);
+        }
+    }
+
+    let mut xys = (0..bw)
+        .flat_map(|x| (0..bh).map(move |y| (x, y)))
+        .collect_vec();
+
+    // try not to process all the empty tiles at the same time
+    // (note that rayon already has a weird execution order)
+    xys.shuffle(&mut thread_rng());
+
+    if let Some(sample) = opts.sample {
+        // the order is already random, so the first few found are a fair sample
+        let dropped = xys
+            .iter()
+            .map(|(x, y)| (i64::from(*x) + lx, i64::from(*y) + ly))
+            .filter(|xy| base_lookup.contains_key(xy))
+            .skip(sample)
+            .collect_vec();
+        info!("sampling {sample} images, leaving out {}", dropped.len());
+        for xy in dropped {
+            base_lookup.remove(&xy);
+        }
+    }
+
+    let total_present = xys
+        .iter()
+        .filter(|(x, y)| base_lookup.contains_key(&(i64::from(*x) + lx, i64::from(*y) + ly)))
+        .count();
+
+    // every base's shrunk copy is held until the overview is built from them
+    let shrunk_res = opts.overview_res;
+    if shrunk_res == 0 || shrunk_res > base_wh {
+        return Err(anyhow!(
+            "overview resolution ({shrunk_res}) must be between 1 and the base size ({base_wh})"
+        ));
+    }
+    if opts.bleed >= tile_wh {
+        return Err(anyhow!(
+            "bleed ({}) must be less than the tile size ({tile_wh})",
+            opts.bleed
+        ));
+    }
+    let shrunk_bytes = total_present as u64 * u64::from(shrunk_res).pow(2) * 4;
+    if shrunk_bytes > MAX_SHRUNK_BYTES {
+        return Err(anyhow!(
+            "the {total_present} shrunk copies at {shrunk_res}px would take {}GB; \
+             try a smaller overview resolution",
+            shrunk_bytes >> 30
+        ));
+    }
+
+    // roughly how many tiles this run will write: every one of the bases not already done,
+    // and the whole overview
+    let todo = xys
+        .iter()
+        .filter(|(x, y)| {
+            let at = (i64::from(*x) + lx, i64::from(*y) + ly);
+            base_lookup.contains_key(&at)
+                && opts.region.is_none_or(|region| region.contains(at.0, at.1))
+                && (opts.force || !exists(overview_zooms, *x, *y))
+        })
+        .collect_vec();
+    let per_base = (0..=detail_zooms)
+        .filter(|neg_zoom| wanted(overview_zooms + detail_zooms - neg_zoom))
+        .map(|neg_zoom| u64::from(tile_per_base >> neg_zoom).pow(2))
+        .sum::<u64>();
+    let overview = (0..overview_zooms)
+        .filter(|zoom| wanted(*zoom))
+        .map(|zoom| {
+            let mul = 2u32.pow(zoom);
+            u64::from((bw * mul).div_ceil(grid_wh)) * u64::from((bh * mul).div_ceil(grid_wh))
+        })
+        .sum::<u64>();
+    let tiles = todo.len() as u64 * per_base + overview;
+    event(Event::Planned { tiles });
+
+    // a deep pyramid can outgrow the disk hours in, so guess how big it'll be first
+    if opts.space_check && !opts.dry_run && sink.is_none() {
+        // the middle of a base at the deepest zoom, where there's most likely something
+        let samples = todo
+            .iter()
+            .take(SPACE_SAMPLES)
+            .filter_map(|(x, y)| {
+                let path = &base_lookup[&(i64::from(*x) + lx, i64::from(*y) + ly)];
+                let img = opts.decode(path).ok()?;
+                let mid = (base_wh - tile_wh) / 2;
+                let tile = img.crop_imm(mid, mid, tile_wh, tile_wh);
+                let mut data = Vec::new();
+                encode(&tile, &opts.img_ops_at(max_zoom, Phase::Detail), &mut data).ok()?;
+                // a block at least each, on most filesystems
+                Some((data.len() as u64).div_ceil(4096) * 4096)
+            })
+            .collect_vec();
+        if tiles > 0 && !samples.is_empty() {
+            let mut per_tile = samples.iter().sum::<u64>() / samples.len() as u64;
+            if opts.retina {
+                per_tile *= 5;
+            }
+            if opts.precompress != Precompress::None {
+                per_tile *= 2;
+            }
+            let needed = tiles * per_tile;
+            let destination = opts.mbtiles.as_deref().unwrap_or(output);
+            let gb = |bytes: u64| bytes as f64 / f64::from(1 << 30);
+            match free_space(destination) {
+                Some(free) if free < needed => {
+                    // an io error, so it's one worth trying again, once there's room
+                    return Err(io::Error::other(format!(
+                        "the {tiles} tiles look like taking about {:.1}GB, but only {:.1}GB \
+                         is free for {destination:?}; see --skip-space-check",
+                        gb(needed),
+                        gb(free)
+                    ))
+                    .into());
+                }
+                Some(free) => info!(
+                    "the {tiles} tiles look like taking about {:.1}GB, of {:.1}GB free",
+                    gb(needed),
+                    gb(free)
+                ),
+                None => debug!("can't tell how much space is free for {destination:?}"),
+            }
+        }
+    }
+
+    phase_end("discover", started);
+
+    let complete = AtomicUsize::new(0);
+    let written = (0..=max_zoom).map(|_| AtomicUsize::new(0)).collect_vec();
+    let written_bytes = (0..=max_zoom).map(|_| AtomicU64::new(0)).collect_vec();
+    let record = |zoom: u32, bytes: u64| {
+        written[zoom as usize].fetch_add(1, Ordering::SeqCst);
+        written_bytes[zoom as usize].fetch_add(bytes, Ordering::SeqCst);
+    };
+    let transparent = AtomicUsize::new(0);
+    let manip_nanos = (0..=max_zoom).map(|_| AtomicU64::new(0)).collect_vec();
+    let save_nanos = (0..=max_zoom).map(|_| AtomicU64::new(0)).collect_vec();
+    // adds the time since `start` to the zoom's bucket, returning it for the per-base totals
+    let add_time = |bucket: &[AtomicU64], zoom: u32, start: Instant| {
+        let nanos = start.elapsed().as_nanos();
+        bucket[zoom as usize].fetch_add(nanos as u64, Ordering::SeqCst);
+        nanos
+    };
+
+    let in_region = |x: u32, y: u32| {
+        opts.region
+            .is_none_or(|region| region.contains(i64::from(x) + lx, i64::from(y) + ly))
+    };
+
+    let png = ImageOps {
+        format: OutputFormat::Png,
+        ..ImageOps::default()
+    };
+    // for this run only, as another's options, or inputs, needn't have anything in common
+    let solid = SolidTiles::default();
+    // shrunk copies are kept between runs, so bases outside the region needn't be decoded
+    let cached_path = |x: u32, y: u32| {
+        shrunk_cache.join(format!("{}_{}.png", i64::from(x) + lx, i64::from(y) + ly))
+    };
+    let cache_fresh = |base: &Path, cached: &Path| -> Result<bool> {
+        Ok(
+            match fs::metadata(cached).and_then(|meta| meta.modified()) {
+                Ok(cached) => cached >= modified(base)?,
+                Err(_) => false,
+            },
+        )
+    };
+    let cache_shrunk = |base: &Path, x: u32, y: u32, img: &DynamicImage| -> Result<()> {
+        let path = cached_path(x, y);
+        if opts.dry_run || cache_fresh(base, &path)? {
+            return Ok(());
+        }
+        create_dir_and_save_with(path, img, &png, &solid)?;
+        Ok(())
+    };
+
+    // every (zoom, x, y) tile covered by the base at x, y, if it's in the region
+    let base_tiles = |x: u32, y: u32| {
+        (0..=detail_zooms)
+            .flat_map(move |neg_zoom| {
+                let tiles = tile_per_base / 2u32.pow(neg_zoom);
+                let zoom = overview_zooms + detail_zooms - neg_zoom;
+                (0..tiles)
+                    .cartesian_product(0..tiles)
+                    .map(move |(ty, tx)| (zoom, x * tiles + tx, y * tiles + ty))
+            })
+            .filter(move |(zoom, _, _)| wanted(*zoom))
+            .filter(move |_| in_region(x, y))
+    };
+
+    let dedup = opts.dedup.then(|| Dedup::new(opts.write_retries));
+    let color_index = match &opts.color_index {
+        Some(path) if !opts.dry_run => Some(ColorIndex::open(path)?),
+        _ => None,
+    };
+    // `suffix` is only for files, as the database and sink refuse retina tiles up front
+    let write_bytes = |zoom: u32, x: u32, y: u32, suffix: &str, data: &[u8]| {
+        let (placed_zoom, placed_x, placed_y) = layout.placed(zoom, x, y);
+        check_in_level(placed_zoom, placed_x, placed_y)?;
+        if let Some(sink) = sink {
+            return Ok(sink(placed_zoom, placed_x, placed_y, data)?);
+        }
+        if let Some(mbtiles) = &mbtiles {
+            return mbtiles.insert(placed_zoom, placed_x, placed_y, data);
+        }
+        let write = |dest: &Path, data: &[u8]| match &dedup {
+            Some(dedup) => dedup.write(dest, data),
+            None => write_with_retries(dest, data, opts.write_retries),
+        };
+        let dest = layout.path_as(zoom, x, y, suffix);
+        write(&dest, data)?;
+        // after the tile, so there's never a copy without it
+        if let Some((suffix, packed)) = opts.precompress.compress(data)? {
+            write(&with_suffix(&dest, suffix), &packed)?;
+        }
+        Ok(())
+    };
+    // `create_dir_and_save`, but deduplicating or into the database if asked
+    let save_as = |zoom: u32, x: u32, y: u32, suffix: &str, img: &DynamicImage| -> Result<u64> {
+        let mut data = Vec::new();
+        let phase = if zoom < overview_zooms {
+            Phase::Overview
+        } else {
+            Phase::Detail
+        };
+        solid
+            .encode(img, &opts.img_ops_at(zoom, phase), &mut data)
+            .with_context(|| anyhow!("encoding the tile at {zoom}/{x}/{y}{suffix}"))?;
+        write_bytes(zoom, x, y, suffix, &data)?;
+        if let Some(color_index) = color_index.as_ref().filter(|_| suffix.is_empty()) {
+            let (zoom, x, y) = layout.named(zoom, x, y);
+            color_index.record(zoom, x, y, img);
+        }
+        Ok(data.len() as u64)
+    };
+    let save = |zoom: u32, x: u32, y: u32, img: &DynamicImage| save_as(zoom, x, y, "", img);
+    // the retina tile, if asked for, from whatever the tile was scaled down from
+    let save_retina = |zoom: u32, x: u32, y: u32, source: &DynamicImage| -> Result<u64> {
+        if !opts.retina {
+            return Ok(0);
+        }
+        let filter = if zoom < overview_zooms {
+            overview_filter
+        } else {
+            detail_filter
+        };
+        let wh = tile_wh * 2;
+        if (source.width(), source.height()) == (wh, wh) {
+            return save_as(zoom, x, y, RETINA_SUFFIX, source);
+        }
+        let scaled = resize(source, wh, filter, opts.linear_resize);
+        save_as(zoom, x, y, RETINA_SUFFIX, &scaled)
+    };
+
+    let blank = |wh: u32| -> Result<Vec<u8>> {
+        let mut blank = Vec::new();
+        encode(&DynamicImage::new_rgba8(wh, wh), &img_ops, &mut blank)?;
+        Ok(blank)
+    };
+    let blank_retina = match opts.fill_blank && opts.retina {
+        true => Some(blank(tile_wh * 2)?),
+        false => None,
+    };
+    let blank = match opts.fill_blank {
+        true => Some(blank(tile_wh)?),
+        false => None,
+    };
+    // write the placeholder, if there is one, for a tile which would otherwise be missing
+    let fill = |zoom: u32, x: u32, y: u32| -> Result<()> {
+        let Some(blank) = &blank else {
+            return Ok(());
+        };
+        if !opts.force && exists(zoom, x, y) {
+            return Ok(());
+        }
+        if opts.dry_run {
+            record(zoom, 0);
+            return Ok(());
+        }
+        write_bytes(zoom, x, y, "", blank)?;
+        let mut bytes = blank.len() as u64;
+        if let Some(blank) = &blank_retina {
+            write_bytes(zoom, x, y, RETINA_SUFFIX, blank)?;
+            bytes += blank.len() as u64;
+        }
+        record(zoom, bytes);
+        Ok(())
+    };
+
+    // the grid is padded to a power of two square; overview tiles entirely in the padding
+    // are never written, so a wide region doesn't gain a load of blank rows
+    let level_wh = |zoom: u32| {
+        let mul = 2u32.pow(zoom);
+        ((bw * mul).div_ceil(grid_wh), (bh * mul).div_ceil(grid_wh))
+    };
+
+    // whether an overview tile covers any of the region, as only those are written
+    let covers_region = |zoom: u32, x: u32, y: u32| {
+        let Some(region) = opts.region else {
+            return true;
+        };
+        let span = i64::from(2u32.pow(overview_zooms - zoom));
+        let (x, y) = (i64::from(x) * span + lx, i64::from(y) * span + ly);
+        x <= region.rx && x + span > region.lx && y <= region.ry && y + span > region.ly
+    };
+
+    // the preview's tiles are rough, so the overview always replaces them, current or not
+    let preview = opts.fast_overview && !opts.dry_run && overview_zooms > 0;
+    if preview {
+        info!("writing a preview of the overview...");
+        event(Event::PhaseStart("preview"));
+        let started = Instant::now();
+        let deepest = overview_zooms.min(FAST_OVERVIEW_ZOOMS) - 1;
+        // bases per side of a tile at the deepest preview level, and how big each is drawn
+        let span = 2u32.pow(overview_zooms - deepest);
+        let piece = (tile_wh / span).max(1);
+        let grey = DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+            piece,
+            piece,
+            Rgba([128, 128, 128, 255]),
+        ));
+        let mut children = xys
+            .par_iter()
+            .filter(|(x, y)| base_lookup.contains_key(&(i64::from(*x) + lx, i64::from(*y) + ly)))
+            .map(|(x, y)| {
+                // stale or from another overview resolution, it's still better than grey
+                let thumb = match opts.open_own(&cached_path(*x, *y)) {
+                    Ok(cached) => resize(&cached, piece, overview_filter, opts.linear_resize),
+                    Err(_) => grey.clone(),
+                };
+                ((*x, *y), thumb)
+            })
+            .collect::<HashMap<_, _>>();
+        let mut child_wh = piece;
+        let mut per_side = span;
+        for zoom in (0..=deepest).rev() {
+            let (w, h) = level_wh(zoom);
+            children = (0..w)
+                .cartesian_product(0..h)
+                .collect_vec()
+                .into_par_iter()
+                .map(|(x, y)| -> Result<Option<((u32, u32), DynamicImage)>> {
+                    let (composite, empty) = composite(&children, child_wh, (x, y), per_side);
+                    if empty {
+                        return Ok(None);
+                    }
+                    let tile = resize(&composite, tile_wh, overview_filter, opts.linear_resize);
+                    if wanted(zoom) && covers_region(zoom, x, y) {
+                        save(zoom, x, y, &tile)?;
+                        save_retina(zoom, x, y, &composite)?;
+                    }
+                    Ok(Some(((x, y), tile)))
+                })
+                .collect::<Result<Vec<_>>>()?
+                .into_iter()
+                .flatten()
+                .collect();
+            child_wh = tile_wh;
+            per_side = 2;
+        }
+        if let Some(mbtiles) = &mbtiles {
+            mbtiles.flush()?;
+        }
+        phase_end("preview", started);
+    }
+
+    info!(
+        "chopping {total_present} images into the detailed zoom levels, keeping shrunk copies..."
+    );
+    event(Event::PhaseStart("detail"));
+    let started = Instant::now();
+    let bar = progress_bar(opts.progress, "detail", total_present);
+
+    let in_flight = opts.max_in_flight.map(Semaphore::new);
+
+    // each base is decoded exactly once; the detailed tiles are written straight away, and only
+    // the (small) shrunk copy is held on to for compositing the lower zoom levels
+    let chop = |(x, y): &(u32, u32)| -> Result<Option<((u32, u32), DynamicImage)>> {
+        if interrupted() {
+            return Ok(None);
+        }
+        let at = (i64::from(*x) + lx, i64::from(*y) + ly);
+        let Some(base) = base_lookup.get(&at) else {
+            for (zoom, dx, dy) in base_tiles(*x, *y) {
+                fill(zoom, dx, dy)?;
+            }
+            return Ok(None);
+        };
+
+        // anything which changes how this base's tiles at a zoom would come out; the
+        // debug output of the ops covers everything the encoder is told, bar its threads
+        let base_modified = modified(base)?;
+        let mtime = base_modified
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        // only mentioned when on, so existing manifests stay current
+        let bleed = match opts.bleed {
+            0 => String::new(),
+            bleed => format!(" bleed {bleed}"),
+        };
+        let precompress = match opts.precompress {
+            Precompress::None => String::new(),
+            precompress => format!(" {precompress:?}"),
+        };
+        let threshold = match opts.transparent_threshold {
+            0 => String::new(),
+            threshold => format!(" faint {threshold}"),
+        };
+        let retina = if opts.retina { " retina" } else { "" };
+        let stamp = |zoom: u32| {
+            let ops = ImageOps {
+                encode_threads: 1,
+                ..opts.img_ops_at(zoom, Phase::Detail)
+            };
+            format!(
+                    "{mtime} {}x{} {overview_zooms} {tile_wh} {:?} {ops:?}{bleed}{precompress}{threshold}{retina}",
+                    i64::from(*x) + lx,
+                    i64::from(*y) + ly,
+                    opts.filter_at(Phase::Detail),
+                )
+        };
+
+        // whether a tile can be kept; by time, if it was written after the base was
+        // changed, as make would, else if it's there and the manifest says it was made
+        // the same way
+        let fresh = |zoom: u32, dx: u32, dy: u32, current: bool| {
+            if by_time {
+                written_since(zoom, dx, dy, base_modified)
+            } else {
+                current && exists(zoom, dx, dy)
+            }
+        };
+
+        // resuming: the crops, and even the decode if the shrunk copy is still good, can be
+        // skipped if all the tiles are already there and were made the same way, though any
+        // transparent tile (which isn't written, unless filling blanks) defeats this
+        let all_current = || {
+            base_tiles(*x, *y).all(|(zoom, dx, dy)| {
+                fresh(
+                    zoom,
+                    dx,
+                    dy,
+                    manifest.is_current(*x, *y, zoom, &stamp(zoom)),
+                )
+            })
+        };
+
+        // outside the region, finished by the run being resumed, or with every tile already
+        // current, the shrunk copy is all that's needed, if it's still good
+        let cached = cached_path(*x, *y);
+        let settled = !in_region(*x, *y) || queue.is_done(at.0, at.1);
+        let complete_already = || !opts.force && all_current();
+        if (settled || complete_already()) && cache_fresh(base, &cached)? {
+            let shrunk = opts
+                .open_own(&cached)
+                .with_context(|| anyhow!("decoding {cached:?}"))?;
+            // else it's from a run with a different overview resolution
+            if (shrunk.width(), shrunk.height()) == (shrunk_res, shrunk_res) {
+                if !settled {
+                    debug!("skipping already complete image {base:?}, without decoding it");
+                    queue.done(at.0, at.1)?;
+                }
+                bar.inc(1);
+                let clear = opts.skip_transparent && is_entirely_transparent(&shrunk);
+                return Ok((!clear).then_some(((*x, *y), shrunk)));
+            }
+        }
+
+        let img = match opts.decode(base) {
+            Ok(img) if (img.width(), img.height()) == (base_wh, base_wh) => img,
+            Ok(img) if opts.allow_mismatched => {
+                let (w, h) = (img.width(), img.height());
+                warn!("skipping {base:?}, which is {w}x{h}, not {base_wh}x{base_wh}");
+                for (zoom, dx, dy) in base_tiles(*x, *y) {
+                    fill(zoom, dx, dy)?;
+                }
+                bar.inc(1);
+                return Ok(None);
+            }
+            Ok(img) => {
+                return Err(anyhow!(
+                    "{base:?} should be {base_wh}x{base_wh} (see --base-size), but is {}x{}",
+                    img.width(),
+                    img.height()
+                ));
+            }
+            Err(e) if opts.skip_errors => {
+                warn!("skipping: {e:#}");
+                unreadable.fetch_add(1, Ordering::SeqCst);
+                for (zoom, dx, dy) in base_tiles(*x, *y) {
+                    fill(zoom, dx, dy)?;
+                }
+                bar.inc(1);
+                return Ok(None);
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let complete = complete.fetch_add(1, Ordering::SeqCst) + 1;
+        // which of the deepest zoom's tiles are entirely transparent; every shallower tile,
+        // and the whole base, is made of these, so the pixels are only looked at once
+        let clear = (0..tile_per_base)
+            .cartesian_product(0..tile_per_base)
+            .map(|(ty, tx)| {
+                let (cx, cy) = (tx * tile_wh, ty * tile_wh);
+                opts.skip_transparent
+                    && is_region_faint(&img, cx, cy, tile_wh, tile_wh, opts.transparent_threshold)
+            })
+            .collect_vec();
+        let is_clear = |tx: u32, ty: u32, mul: u32| {
+            (0..mul)
+                .cartesian_product(0..mul)
+                .all(|(oy, ox)| clear[((ty * mul + oy) * tile_per_base + tx * mul + ox) as usize])
+        };
+        if is_clear(0, 0, tile_per_base) {
+            debug!("skipping entirely transparent image {base:?}");
+            cache_shrunk(
+                base,
+                *x,
+                *y,
+                &DynamicImage::new_rgba8(shrunk_res, shrunk_res),
+            )?;
+            transparent.fetch_add(base_tiles(*x, *y).count(), Ordering::SeqCst);
+            for (zoom, dx, dy) in base_tiles(*x, *y) {
+                fill(zoom, dx, dy)?;
+            }
+            queue.done(at.0, at.1)?;
+            bar.inc(1);
+            return Ok(None);
+        }
+        let shrunk = resize(&img, shrunk_res, overview_filter, opts.linear_resize);
+        cache_shrunk(base, *x, *y, &shrunk)?;
+
+        if !opts.force && all_current() {
+            debug!("skipping already complete image {base:?}");
+            queue.done(at.0, at.1)?;
+            bar.inc(1);
+            return Ok(Some(((*x, *y), shrunk)));
+        }
+
+        // the margin is enough for the shallowest detailed zoom, which needs the most
+        let bled = (opts.bleed > 0).then(|| {
+            Bled::new(&img, opts.bleed * tile_per_base, |dx, dy| {
+                let path = base_lookup.get(&(i64::from(*x) + lx + dx, i64::from(*y) + ly + dy))?;
+                match opts.decode(path) {
+                    Ok(img) if (img.width(), img.height()) == (base_wh, base_wh) => Some(img),
+                    // it'll be complained about when it's tiled itself
+                    _ => None,
+                }
+            })
+        });
+
+        let mut time_manip = 0;
+        let mut time_save = 0;
+        for neg_zoom in 0..=detail_zooms {
+            let mul = 2u32.pow(neg_zoom);
+            let tiles = tile_per_base / mul;
+            let step = tile_wh * mul;
+            // at zoom `overview_zooms` there's one tile per base; e.g. (4096 / 256px/tile) = 16
+            // tiles per base gives four more zoom levels, each doubling the tiles per base
+            let zoom = overview_zooms + detail_zooms - neg_zoom;
+            if !wanted(zoom) {
+                continue;
+            }
+            let stamp = stamp(zoom);
+            let current = manifest.is_current(*x, *y, zoom, &stamp);
+            for ty in 0..tiles {
+                for tx in 0..tiles {
+                    // this zoom isn't marked as done, so is redone next time
+                    if interrupted() {
+                        return Ok(None);
+                    }
+                    let dx = x * tiles + tx;
+                    let dy = y * tiles + ty;
+                    if !opts.force && fresh(zoom, dx, dy, current) {
+                        continue;
+                    }
+
+                    let start = Instant::now();
+                    let (cx, cy) = (tx * step, ty * step);
+                    if !base_square_fits(&img, base, (tx, ty), step, opts.skip_errors)? {
+                        continue;
+                    }
+                    // so transparent tiles are never copied out
+                    if is_clear(tx, ty, mul) {
+                        debug!("skipping transparent cropped tile at {x}x{y} -> {tx}x{ty}");
+                        transparent.fetch_add(1, Ordering::SeqCst);
+                        fill(zoom, dx, dy)?;
+                        continue;
+                    }
+                    if opts.dry_run {
+                        record(zoom, 0);
+                        continue;
+                    }
+                    let crop = match &bled {
+                        Some(bled) => bled.tile(
+                            (cx, cy, step),
+                            tile_wh,
+                            opts.bleed,
+                            detail_filter,
+                            opts.linear_resize,
+                        ),
+                        None => {
+                            let crop = img.crop_imm(cx, cy, step, step);
+                            resize(&crop, tile_wh, detail_filter, opts.linear_resize)
+                        }
+                    };
+                    time_manip += add_time(&manip_nanos, zoom, start);
+                    let start = Instant::now();
+                    let mut bytes = save(zoom, dx, dy, &crop)?;
+                    if opts.retina {
+                        // from the base again, rather than scaling up the tile
+                        let crop = match &bled {
+                            Some(bled) => bled.tile(
+                                (cx, cy, step),
+                                tile_wh * 2,
+                                opts.bleed * 2,
+                                detail_filter,
+                                opts.linear_resize,
+                            ),
+                            None => img.crop_imm(cx, cy, step, step),
+                        };
+                        bytes += save_retina(zoom, dx, dy, &crop)?;
+                    }
+                    record(zoom, bytes);
+                    time_save += add_time(&save_nanos, zoom, start);
+                    debug!("saved {tx}x{ty} in {x}x{y} as {dx}x{dy}");
+                }
+            }
+            manifest.update(*x, *y, zoom, &stamp)?;
+        }
+        queue.done(at.0, at.1)?;
+
+        event(Event::Base {
+            path: base,
+            x: at.0,
+            y: at.1,
+            manip: Duration::from_nanos(time_manip as u64),
+            save: Duration::from_nanos(time_save as u64),
+        });
+        let time_manip = time_manip as f64 / 1e9;
+        let time_save = time_save as f64 / 1e9;
+        let msg = format!("processed {complete}/{total_present}: {base:?} (manip {time_manip:.2}s, save {time_save:.2}s)");
+        // the bar already shows progress, so only log it when nobody's watching the bar
+        if bar.is_hidden() {
+            info!("{msg}");
+        } else {
+            debug!("{msg}");
+        }
+        bar.inc(1);
+
+        Ok(Some(((*x, *y), shrunk)))
+    };
+    // permits are taken here, outside the pool's work, and held until the base's tiles are done,
+    // as that's when the decoded image is dropped; a base waiting for one inside the pool could
+    // be stolen by a holder's encode, which would then never finish
+    let chopped = Mutex::new(Vec::new());
+    let failed = AtomicBool::new(false);
+    rayon::scope(|scope| {
+        for xy in &xys {
+            if failed.load(Ordering::SeqCst) {
+                break;
+            }
+            let permit = in_flight
+                .as_ref()
+                .map(|in_flight| in_flight.acquire_helping(decoded_per_base));
+            let (chop, chopped, failed) = (&chop, &chopped, &failed);
+            scope.spawn(move |_| {
+                let result = chop(xy);
+                drop(permit);
+                if result.is_err() {
+                    failed.store(true, Ordering::SeqCst);
+                }
+                chopped.lock().expect("poisoned").push(result);
+            });
+        }
+    });
+    let shrunk = chopped
+        .into_inner()
+        .expect("poisoned")
+        .into_iter()
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .flatten()
+        .collect::<HashMap<_, _>>();
+    // every base that's finished with moves the bar on, whether or not it's shown
+    let bases_done = bar.position() as usize;
+    bar.finish_and_clear();
+    phase_end("detail", started);
+
+    let total_non_blank = shrunk.len();
+
+    // an interrupted run hasn't looked at every base, so would colour the rest as transparent
+    match &opts.coverage_map {
+        Some(path) if !opts.dry_run && !interrupted() => {
+            write_coverage_map(path, (bw, bh), |x, y| {
+                if shrunk.contains_key(&(x, y)) {
+                    Coverage::Drawn
+                } else if base_lookup.contains_key(&(i64::from(x) + lx, i64::from(y) + ly)) {
+                    Coverage::Transparent
+                } else {
+                    Coverage::Missing
+                }
+            })?
+        }
+        _ => (),
+    }
+
+    let dump = |name: String, img: &DynamicImage| -> Result<()> {
+        let Some(dir) = &opts.dump_intermediate else {
+            return Ok(());
+        };
+        create_dir_and_save_with(dir.join(name), img, &png, &solid)?;
+        Ok(())
+    };
+    shrunk
+        .par_iter()
+        .try_for_each(|((x, y), img)| dump(format!("shrunk/{x}_{y}.png"), img))?;
+    // told whether it was interrupted, as a deadline can pass after the last check
+    let stats = |written: Vec<AtomicUsize>, written_bytes: Vec<AtomicU64>, interrupted| TileStats {
+        bases: total_present,
+        non_blank: total_non_blank,
+        tiles: written.into_iter().map(AtomicUsize::into_inner).collect(),
+        bytes: written_bytes
+            .into_iter()
+            .map(AtomicU64::into_inner)
+            .collect(),
+        transparent_tiles: transparent.load(Ordering::SeqCst),
+        unreadable: unreadable.load(Ordering::SeqCst),
+        deduplicated: dedup.as_ref().map_or(0, Dedup::linked),
+        manip_time: durations(&manip_nanos),
+        save_time: durations(&save_nanos),
+        interrupted,
+        bases_done,
+    };
+
+    let level_tiles = |zoom: u32| {
+        let (w, h) = level_wh(zoom);
+        (0..w)
+            .cartesian_product(0..h)
+            .filter(|(x, y)| covers_region(zoom, *x, *y))
+            .count()
+    };
+
+    if opts.dry_run {
+        for zoom in (0..overview_zooms).filter(|zoom| wanted(*zoom)) {
+            written[zoom as usize].fetch_add(level_tiles(zoom), Ordering::SeqCst);
+        }
+        return Ok(stats(written, written_bytes, interrupted()));
+    }
+
+    info!(
+        "{total_non_blank} (shrunk) images are non-empty, compositing the overview zoom levels..."
+    );
+    event(Event::PhaseStart("overview"));
+    let started = Instant::now();
+
+    let shallowest = (0..overview_zooms)
+        .find(|zoom| wanted(*zoom))
+        .unwrap_or(overview_zooms);
+
+    // each overview tile's stamp covers how it's made and every base under it, by hashing
+    // the bases' hashes up the levels as the images are composited; so only tiles over an
+    // added, changed or removed base are made again. the hasher isn't promised to be stable
+    // between rust releases, which would just remake the overview once
+    let overview_stamp = |zoom: u32, hash: u64| {
+        let ops = ImageOps {
+            encode_threads: 1,
+            ..opts.img_ops_at(zoom, Phase::Overview)
+        };
+        let retina = if opts.retina { " retina" } else { "" };
+        format!(
+            "{hash:016x} {lx}x{ly} {grid_wh} {tile_wh} {shrunk_res} {:?} {} {} {ops:?}{retina}",
+            opts.filter_at(Phase::Overview),
+            opts.linear_resize,
+            opts.transparent_threshold
+        )
+    };
+    let mut hashes = xys
+        .iter()
+        .filter_map(|&(x, y)| {
+            let path = base_lookup.get(&(i64::from(x) + lx, i64::from(y) + ly))?;
+            let mut hasher = DefaultHasher::new();
+            path.hash(&mut hasher);
+            modified(path).ok().hash(&mut hasher);
+            Some(((x, y), hasher.finish()))
+        })
+        .collect::<HashMap<_, _>>();
+    let mut stamps = HashMap::new();
+    for zoom in (shallowest..overview_zooms).rev() {
+        let (w, h) = level_wh(zoom);
+        hashes = (0..w)
+            .cartesian_product(0..h)
+            .map(|(x, y)| {
+                let mut hasher = DefaultHasher::new();
+                for (cx, cy) in (0..2).cartesian_product(0..2) {
+                    hashes.get(&(x * 2 + cx, y * 2 + cy)).hash(&mut hasher);
+                }
+                ((x, y), hasher.finish())
+            })
+            .collect();
+        for (&(x, y), &hash) in &hashes {
+            stamps.insert((zoom, x, y), overview_stamp(zoom, hash));
+        }
+    }
+
+    // the tiles to write, and, working down, those to composite: every tile to write, and
+    // all four children of anything composited, as it's made from them
+    let mut to_write = HashSet::new();
+    let mut to_composite = HashMap::new();
+    let mut parents = HashSet::new();
+    for zoom in shallowest..overview_zooms {
+        let (w, h) = level_wh(zoom);
+        let mut level = HashSet::new();
+        for (x, y) in (0..w).cartesian_product(0..h) {
+            let stale = opts.force
+                || (preview && zoom < FAST_OVERVIEW_ZOOMS)
+                || !manifest.is_current(x, y, zoom, &stamps[&(zoom, x, y)])
+                || !exists(zoom, x, y);
+            if wanted(zoom) && covers_region(zoom, x, y) && stale {
+                to_write.insert((zoom, x, y));
+                level.insert((x, y));
+            } else if parents.contains(&(x / 2, y / 2)) {
+                level.insert((x, y));
+            }
+        }
+        parents = level.clone();
+        to_composite.insert(zoom, level);
+    }
+    let overview_tiles = to_write.len();
+    let current = (0..overview_zooms)
+        .filter(|zoom| wanted(*zoom))
+        .map(level_tiles)
+        .sum::<usize>()
+        - overview_tiles;
+    if current > 0 {
+        info!("{current} overview tiles are already up to date");
+    }
+    let bar = progress_bar(opts.progress, "overview", overview_tiles);
+
+    // built bottom up, each tile from the four below it, so only two levels are ever in memory;
+    // the shrunk bases act as the level below the deepest overview level. levels deeper than
+    // the max zoom are still needed to build the shallower ones, they just aren't written
+    let mut children = shrunk;
+    let mut child_wh = shrunk_res;
+    for zoom in (shallowest..overview_zooms).rev() {
+        let level = to_composite[&zoom]
+            .iter()
+            .copied()
+            .collect_vec()
+            .into_par_iter()
+            .map(|(x, y)| -> Result<Option<((u32, u32), DynamicImage)>> {
+                if interrupted() {
+                    return Ok(None);
+                }
+                let start = Instant::now();
+                let (composite, empty) = composite(&children, child_wh, (x, y), 2);
+                if !empty {
+                    dump(format!("composite/{zoom}/{x}_{y}.png"), &composite)?;
+                }
+                let tile = resize(&composite, tile_wh, overview_filter, opts.linear_resize);
+                add_time(&manip_nanos, zoom, start);
+                if to_write.contains(&(zoom, x, y)) {
+                    let start = Instant::now();
+                    let bytes = save(zoom, x, y, &tile)? + save_retina(zoom, x, y, &composite)?;
+                    add_time(&save_nanos, zoom, start);
+                    record(zoom, bytes);
+                    manifest.update(x, y, zoom, &stamps[&(zoom, x, y)])?;
+                    bar.inc(1);
+                }
+                Ok((!empty).then_some(((x, y), tile)))
+            })
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .flatten()
+            .collect::<HashMap<_, _>>();
+        children = level;
+        child_wh = tile_wh;
+    }
+    bar.finish_and_clear();
+    phase_end("overview", started);
+
+    drop(children);
+
+    // with whatever was done, even if interrupted, as the tiles are there
+    if let (Some(color_index), Some(path)) = (&color_index, &opts.color_index) {
+        color_index.write(path)?;
+    }
+
+    // the overview stops too, but it's always remade in full, so a rerun fixes it up
+    if interrupted() {
+        if let Some(mbtiles) = &mbtiles {
+            mbtiles.flush()?;
+        }
+        return Ok(stats(written, written_bytes, true));
+    }
+    queue.finish()?;
+
+    // as the viewers see them, in the bigger pyramid
+    let zooms = (min_zoom + opts.zoom_offset, max_zoom + opts.zoom_offset);
+    if let Some(mbtiles) = &mbtiles {
+        let name = opts
+            .mbtiles
+            .as_deref()
+            .and_then(Path::file_stem)
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        mbtiles.finish(&[
+            ("name", name),
+            ("format", img_ops.format.extension().to_string()),
+            ("minzoom", zooms.0.to_string()),
+            ("maxzoom", zooms.1.to_string()),
+        ])?;
+        return Ok(stats(written, written_bytes, false));
+    }
+
+    // this run's zoom 0 tile is this much of the bigger pyramid's
+    let scale = f64::from(2u32.pow(opts.zoom_offset)).recip();
+    let left = f64::from(opts.tile_offset.x) * scale;
+    let top = f64::from(opts.tile_offset.y) * scale;
+    let covered = (
+        (left, top),
+        (
+            left + f64::from(bw) / f64::from(grid_wh) * scale,
+            top + f64::from(bh) / f64::from(grid_wh) * scale,
+        ),
+    );
+    write_tilejson(
+        &output.join("tilejson.json"),
+        &layout,
+        zooms,
+        covered,
+        opts.precompress,
+    )?;
+    if opts.viewer {
+        write_viewer(
+            &output.join("index.html"),
+            &layout,
+            tile_wh,
+            opts.retina,
+            zooms,
+            covered,
+            opts.precompress,
+        )?;
+    }
+
+    Ok(stats(written, written_bytes, false))
+}
+
+/// the `per_side` square of `children` making up the tile at `x`, `y` a level or more above
+/// them, side by side at full size, and whether none of them were there
+fn composite(
+    children: &HashMap<(u32, u32), DynamicImage>,
+    child_wh: u32,
+    (x, y): (u32, u32),
+    per_side: u32,
+) -> (DynamicImage, bool) {
+    let mut composite = DynamicImage::new_rgba8(child_wh * per_side, child_wh * per_side);
+    let mut empty = true;
+    for (cx, cy) in (0..per_side).cartesian_product(0..per_side) {
+        let Some(child) = children.get(&(x * per_side + cx, y * per_side + cy)) else {
+            continue;
+        };
+        // the children don't overlap, and the canvas is transparent, so a straight
+        // copy is exact where blending would round
+        image::imageops::replace(
+            &mut composite,
+            child,
+            i64::from(cx * child_wh),
+            i64::from(cy * child_wh),
+        );
+        empty = false;
+    }
+    (composite, empty)
+}
+
+/// write the tiles for a single decoded base, which is one whole tile at x, y at `zoom`, and is
+/// split further at each deeper level; returns how many tiles were written
+///
+/// there are no overview levels, as they'd need the neighbouring bases, and no manifest, so
+/// existing tiles are kept unless forced. dry runs, blank filling and progress are ignored
+pub fn tile_base(
+    img: &DynamicImage,
+    (x, y): (u32, u32),
+    zoom: u32,
+    output: &Path,
+    opts: &TileOptions,
+) -> Result<usize, TileError> {
+    // the caller already says where the base goes
+    let layout = TileLayout {
+        zoom_offset: 0,
+        tile_offset: TileOffset::default(),
+        ..TileLayout::new(output, opts)
+    };
+    let mut written = 0;
+    for_each_base_tile(
+        img,
+        (x, y),
+        zoom,
+        opts,
+        &SolidTiles::default(),
+        |zoom, x, y| !opts.force && layout.path(zoom, x, y).exists(),
+        |zoom, x, y, data| {
+            let dest = layout.path(zoom, x, y);
+            write_with_retries(&dest, &data, opts.write_retries)?;
+            if let Some((suffix, packed)) = opts.precompress.compress(&data)? {
+                write_with_retries(&with_suffix(&dest, suffix), &packed, opts.write_retries)?;
+            }
+            written += 1;
+            Ok(())
+        },
+    )?;
+    Ok(written)
+}
+
+/// a tile's zoom, x, y and encoded bytes
+pub type EncodedTile = (u32, u32, u32, Vec<u8>);
+
+/// [`tile_base`], but returning each tile instead of writing it
+pub fn encode_base(
+    img: &DynamicImage,
+    (x, y): (u32, u32),
+    zoom: u32,
+    opts: &TileOptions,
+) -> Result<Vec<EncodedTile>, TileError> {
+    let mut tiles = Vec::new();
+    for_each_base_tile(
+        img,
+        (x, y),
+        zoom,
+        opts,
+        &SolidTiles::default(),
+        |_, _, _| false,
+        |zoom, x, y, data| {
+            tiles.push((zoom, x, y, data));
+            Ok(())
+        },
+    )?;
+    Ok(tiles)
+}
+
+/// encode every tile of a base which isn't entirely transparent, and which `skip` doesn't
+/// reject, handing each to `sink`
+fn for_each_base_tile(
+    img: &DynamicImage,
+    (x, y): (u32, u32),
+    zoom: u32,
+    opts: &TileOptions,
+    solid: &SolidTiles,
+    skip: impl Fn(u32, u32, u32) -> bool,
+    mut sink: impl FnMut(u32, u32, u32, Vec<u8>) -> Result<()>,
+) -> Result<()> {
+    let tile_per_base = opts.tiles_per_base()?;
+    let base_wh = opts.base_size;
+    let tile_wh = opts.tile_size;
+    let padded = opts.padded(img);
+    let img = padded.as_ref().unwrap_or(img);
+    if (img.width(), img.height()) != (base_wh, base_wh) {
+        return Err(anyhow!(
+            "image should be {base_wh}x{base_wh} (the base size), not {}x{}",
+            img.width(),
+            img.height()
+        ));
+    }
+    if zoom >= 32 || x >> zoom != 0 || y >> zoom != 0 {
+        return Err(anyhow!("{x}x{y} is outside zoom level {zoom}"));
+    }
+    let filter = opts.filter_at(Phase::Detail).filter_type();
+    let detail_zooms = tile_per_base.trailing_zeros();
+
+    for neg_zoom in 0..=detail_zooms {
+        let mul = 2u32.pow(neg_zoom);
+        let tiles = tile_per_base / mul;
+        let step = tile_wh * mul;
+        let tile_zoom = zoom + detail_zooms - neg_zoom;
+        if opts.min_zoom.is_some_and(|min| tile_zoom < min)
+            || opts.max_zoom.is_some_and(|max| tile_zoom > max)
+        {
+            continue;
+        }
+        for (ty, tx) in (0..tiles).cartesian_product(0..tiles) {
+            let (dx, dy) = (x * tiles + tx, y * tiles + ty);
+            check_in_level(tile_zoom, dx, dy)?;
+            if skip(tile_zoom, dx, dy) {
+                continue;
+            }
+            let (cx, cy) = (tx * step, ty * step);
+            check_square(img, cx, cy, step)?;
+            if opts.skip_transparent
+                && is_region_faint(img, cx, cy, step, step, opts.transparent_threshold)
+            {
+                continue;
+            }
+            let crop = img.crop_imm(cx, cy, step, step);
+            let crop = resize(&crop, tile_wh, filter, opts.linear_resize);
+            let mut data = Vec::new();
+            solid
+                .encode(&crop, &opts.img_ops_at(tile_zoom, Phase::Detail), &mut data)
+                .with_context(|| anyhow!("encoding tile {dx}x{dy} at zoom {tile_zoom}"))?;
+            sink(tile_zoom, dx, dy, data)?;
+        }
+    }
+    Ok(())
+}
+
+fn durations(nanos: &[AtomicU64]) -> Vec<Duration> {
+    nanos
+        .iter()
+        .map(|nanos| Duration::from_nanos(nanos.load(Ordering::SeqCst)))
+        .collect()
+}
+
+fn modified(path: &Path) -> Result<SystemTime> {
+    fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .with_context(|| anyhow!("reading modification time of {path:?}"))
+}
+
+/// hidden if disabled, or if stderr isn't a terminal
+fn progress_bar(enabled: bool, what: &'static str, len: usize) -> ProgressBar {
+    if !enabled {
+        return ProgressBar::hidden();
+    }
+    ProgressBar::new(len as u64)
+        .with_style(
+            ProgressStyle::with_template("{msg} {wide_bar} {pos}/{len} [{elapsed}, eta {eta}]")
+                .expect("static template"),
+        )
+        .with_message(what)
+}
+
+/// describe the pyramid for map viewers, per <https://github.com/mapbox/tilejson-spec/tree/master/3.0.0>
+///
+/// `covered` is the top left and bottom right of the part of the world which has data, as
+/// fractions of the way across and down; the spec wants
+/// this in longitude and latitude, so it's projected as if the pyramid was web mercator
+fn write_tilejson(
+    path: &Path,
+    layout: &TileLayout,
+    (min_zoom, max_zoom): (u32, u32),
+    ((left, top), (right, bottom)): ((f64, f64), (f64, f64)),
+    precompress: Precompress,
+) -> Result<()> {
+    let lon = |f: f64| f * 360. - 180.;
+    let lat = |f: f64| {
+        (std::f64::consts::PI * (1. - 2. * f))
+            .sinh()
+            .atan()
+            .to_degrees()
+    };
+    let scheme = match layout.scheme {
+        Scheme::Xyz => "xyz",
+        Scheme::Tms => "tms",
+    };
+    let json = format!(
+        r#"{{
+  "tilejson": "3.0.0",
+  "tiles": ["{template}"],
+  "scheme": "{scheme}",
+  "minzoom": {min_zoom},
+  "maxzoom": {max_zoom},
+  "bounds": [{west}, {south}, {east}, {north}]
+}}
+"#,
+        template = layout.template(""),
+        west = lon(left),
+        south = lat(bottom),
+        east = lon(right),
+        north = lat(top),
+    );
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut out = tempfile_fast::Sponge::new_for(path)?;
+    out.write_all(json.as_bytes())?;
+    out.commit()
+        .with_context(|| anyhow!("writing tilejson to {path:?}"))?;
+    write_precompressed(path, json.as_bytes(), precompress)
+}
+
+/// a page showing the pyramid with leaflet, which is loaded from unpkg
+///
+/// the tiles aren't really of anywhere, so this uses leaflet's flat crs, not web mercator;
+/// `covered` is as for [`write_tilejson`]. with `retina`, high dpi screens get the `@2x` tiles
+fn write_viewer(
+    path: &Path,
+    layout: &TileLayout,
+    tile_wh: u32,
+    retina: bool,
+    (min_zoom, max_zoom): (u32, u32),
+    ((left, top), (right, bottom)): ((f64, f64), (f64, f64)),
+    precompress: Precompress,
+) -> Result<()> {
+    let html = format!(
+        r#"<!doctype html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>tiledir</title>
+<link rel="stylesheet" href="https://unpkg.com/leaflet@1.9.4/dist/leaflet.css">
+<script src="https://unpkg.com/leaflet@1.9.4/dist/leaflet.js"></script>
+<style>html, body, #map {{ height: 100%; margin: 0; }}</style>
+</head>
+<body>
+<div id="map"></div>
+<script>
+const tms = {tms};
+const size = {tile_wh};
+// leaflet's own tms flip needs a bounded world, which the flat crs isn't
+const Tiles = L.TileLayer.extend({{
+  getTileUrl(c) {{
+    const y = tms ? (1 << c.z) - 1 - c.y : c.y;
+    const r = L.Browser.retina ? "{retina}" : "";
+    let quadkey = "";
+    for (let bit = c.z - 1; bit >= 0; bit--) {{
+      quadkey += ((c.x >> bit) & 1) + 2 * ((c.y >> bit) & 1);
+    }}
+    return L.Util.template(this._url, {{ z: c.z, x: c.x, y, r, quadkey }});
+  }},
+}});
+const bounds = [[-{bottom} * size, {left} * size], [-{top} * size, {right} * size]];
+const map = L.map("map", {{ crs: L.CRS.Simple, minZoom: {min_zoom}, maxZoom: {over_zoom} }});
+new Tiles("{template}", {{
+  tileSize: size,
+  minZoom: {min_zoom},
+  maxNativeZoom: {max_zoom},
+  maxZoom: {over_zoom},
+  bounds,
+  noWrap: true,
+}}).addTo(map);
+map.fitBounds(bounds);
+</script>
+</body>
+</html>
+"#,
+        tms = layout.scheme == Scheme::Tms,
+        over_zoom = max_zoom + 2,
+        retina = if retina { RETINA_SUFFIX } else { "" },
+        template = layout.template("{r}"),
+    );
+    create_dir_and_write_bytes(path, html.as_bytes())
+        .with_context(|| anyhow!("writing viewer to {path:?}"))?;
+    write_precompressed(path, html.as_bytes(), precompress)
+}
+
+/// what the coverage map shows for a base
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Coverage {
+    /// there's an input, and something in it
+    Drawn,
+    /// there's an input, but it's entirely transparent, or was skipped as unreadable
+    Transparent,
+    /// there's no input
+    Missing,
+}
+
+/// a png with a pixel for each base in the `w` by `h` bounds, top left first, coloured by
+/// what `coverage` says is there
+fn write_coverage_map(
+    path: &Path,
+    (w, h): (u32, u32),
+    coverage: impl Fn(u32, u32) -> Coverage,
+) -> Result<()> {
+    let map = RgbImage::from_fn(w, h, |x, y| {
+        image::Rgb(match coverage(x, y) {
+            Coverage::Drawn => [46, 160, 67],
+            Coverage::Transparent => [200, 200, 200],
+            Coverage::Missing => [215, 58, 73],
+        })
+    });
+    let png = ImageOps {
+        format: OutputFormat::Png,
+        ..ImageOps::default()
+    };
+    create_dir_and_save(path, &DynamicImage::ImageRgb8(map), &png)
+        .with_context(|| anyhow!("writing coverage map to {path:?}"))?;
+    Ok(())
+}
+
+/// write the compressed copy of `data`, already written to `path`, beside it, if asked for
+fn write_precompressed(path: &Path, data: &[u8], precompress: Precompress) -> Result<()> {
+    if let Some((suffix, packed)) = precompress.compress(data)? {
+        let path = with_suffix(path, suffix);
+        create_dir_and_write_bytes(&path, &packed)
+            .with_context(|| anyhow!("writing compressed copy {path:?}"))?;
+    }
+    Ok(())
+}
+
+/// whether the `wh` square of tile `tx`, `ty` is inside `base`'s `img`; if not, that's an
+/// error, or, with `skip_errors`, a warning and `false`
+fn base_square_fits(
+    img: &DynamicImage,
+    base: &Path,
+    (tx, ty): (u32, u32),
+    wh: u32,
+    skip_errors: bool,
+) -> Result<bool> {
+    match check_square(img, tx * wh, ty * wh, wh) {
+        Ok(()) => Ok(true),
+        Err(e) if skip_errors => {
+            warn!("skipping {tx}x{ty} in {base:?}: {e:#}");
+            Ok(false)
+        }
+        Err(e) => Err(e.context(format!("tiling {base:?}"))),
+    }
+}
+
+/// fail if the square runs off the edge, where `crop_imm` would quietly return less
+fn check_square(img: &DynamicImage, x: u32, y: u32, wh: u32) -> Result<()> {
+    let fits = |start: u32, len: u32| start.checked_add(wh).is_some_and(|end| end <= len);
+    if !fits(x, img.width()) || !fits(y, img.height()) {
+        return Err(anyhow!(
+            "{wh}px square at {x}x{y} is outside the {}x{} image",
+            img.width(),
+            img.height()
+        ));
+    }
+    Ok(())
+}
+
+/// scale a square image, weighting colours by their alpha (i.e. premultiplied), so transparent
+/// pixels, whatever colour they happen to be stored as, don't bleed fringes into partly
+/// transparent edges
+///
+/// with `linear`, colours are averaged as light rather than as their srgb encoding, which
+/// stops fine detail and gradients darkening as they're shrunk
+///
+/// images with alpha, or being made linear, go through f32 to do this, and come back at their
+/// original depth
+fn resize(img: &DynamicImage, wh: u32, filter: FilterType, linear: bool) -> DynamicImage {
+    resize_rect(img, wh, wh, filter, linear)
+}
+
+/// `resize`, but to any shape
+fn resize_rect(
+    img: &DynamicImage,
+    w: u32,
+    h: u32,
+    filter: FilterType,
+    linear: bool,
+) -> DynamicImage {
+    if !img.color().has_alpha() && !linear {
+        return img.resize_exact(w, h, filter);
+    }
+    let mut premultiplied = img.to_rgba32f();
+    for p in premultiplied.pixels_mut() {
+        let alpha = p.0[3];
+        for c in &mut p.0[..3] {
+            if linear {
+                *c = srgb_to_linear(*c);
+            }
+            *c *= alpha;
+        }
+    }
+    let mut resized = image::imageops::resize(&premultiplied, w, h, filter);
+    for p in resized.pixels_mut() {
+        let alpha = p.0[3];
+        for c in &mut p.0[..3] {
+            if alpha > 0. {
+                *c /= alpha;
+            }
+            if linear {
+                *c = linear_to_srgb(*c);
+            }
+        }
+    }
+    let resized = DynamicImage::ImageRgba32F(resized);
+    match img {
+        DynamicImage::ImageRgba8(_) | DynamicImage::ImageLumaA8(_) => {
+            DynamicImage::ImageRgba8(resized.to_rgba8())
+        }
+        DynamicImage::ImageRgba16(_) | DynamicImage::ImageLumaA16(_) => {
+            DynamicImage::ImageRgba16(resized.to_rgba16())
+        }
+        DynamicImage::ImageRgb8(_) | DynamicImage::ImageLuma8(_) => {
+            DynamicImage::ImageRgb8(resized.to_rgb8())
+        }
+        DynamicImage::ImageRgb16(_) | DynamicImage::ImageLuma16(_) => {
+            DynamicImage::ImageRgb16(resized.to_rgb16())
+        }
+        DynamicImage::ImageRgb32F(_) => DynamicImage::ImageRgb32F(resized.to_rgb32f()),
+        _ => resized,
+    }
+}
+
+fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(c: f32) -> f32 {
+    let c = c.clamp(0., 1.);
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1. / 2.4) - 0.055
+    }
+}
+
+/// draw the image over a solid background, losing the alpha channel, and any depth past 8 bits
+fn flatten(img: &DynamicImage, background: [u8; 3]) -> RgbImage {
+    let img = img.to_rgba8();
+    let mut flat = RgbImage::new(img.width(), img.height());
+    for (to, from) in flat.pixels_mut().zip(img.pixels()) {
+        let [r, g, b, a] = from.0;
+        let a = u16::from(a);
+        let blend = |fg: u8, bg: u8| ((u16::from(fg) * a + u16::from(bg) * (255 - a)) / 255) as u8;
+        to.0 = [
+            blend(r, background[0]),
+            blend(g, background[1]),
+            blend(b, background[2]),
+        ];
+    }
+    flat
+}
+
+/// images without an alpha channel are never transparent
+pub fn is_entirely_transparent(img: &DynamicImage) -> bool {
+    is_region_transparent(img, 0, 0, img.width(), img.height())
+}
+
+/// `is_entirely_transparent` for part of the image, without copying it out; stops at the
+/// first visible pixel. panics if the region isn't inside the image
+pub fn is_region_transparent(img: &DynamicImage, x: u32, y: u32, w: u32, h: u32) -> bool {
+    is_region_faint(img, x, y, w, h, 0)
+}
+
+/// `is_region_transparent`, but counting pixels with an alpha up to `threshold`, out of 255
+/// whatever the depth, as transparent too
+pub fn is_region_faint(img: &DynamicImage, x: u32, y: u32, w: u32, h: u32, threshold: u8) -> bool {
+    let region = (x, y, w, h);
+    let width = img.width();
+    let t8 = threshold;
+    let t16 = u16::from(threshold) * 257;
+    let t32 = f32::from(threshold) / 255.;
+    match img {
+        DynamicImage::ImageRgba8(img) => alpha_all(img.as_raw(), width, 4, region, |a| a <= t8),
+        DynamicImage::ImageRgba16(img) => alpha_all(img.as_raw(), width, 4, region, |a| a <= t16),
+        DynamicImage::ImageRgba32F(img) => alpha_all(img.as_raw(), width, 4, region, |a| a <= t32),
+        DynamicImage::ImageLumaA8(img) => alpha_all(img.as_raw(), width, 2, region, |a| a <= t8),
+        DynamicImage::ImageLumaA16(img) => alpha_all(img.as_raw(), width, 2, region, |a| a <= t16),
+        _ => false,
+    }
+}
+
+/// whether `pred` holds for the alpha, the last channel, of every pixel in the region
+fn alpha_all<T: Copy>(
+    raw: &[T],
+    width: u32,
+    channels: usize,
+    (x, y, w, h): (u32, u32, u32, u32),
+    pred: impl Fn(T) -> bool,
+) -> bool {
+    (y..y + h).all(|row| {
+        let start = (row as usize * width as usize + x as usize) * channels;
+        raw[start..start + w as usize * channels]
+            .chunks_exact(channels)
+            .all(|p| pred(p[channels - 1]))
+    })
+}
+
+/// encode `img` into `path`, atomically, creating any missing parent directories; an image
+/// of a single colour is encoded at the fastest settings
+///
+/// returns the size of the encoded file
+pub fn create_dir_and_save(
+    path: impl AsRef<Path>,
+    img: &DynamicImage,
+    img_ops: &ImageOps,
+) -> Result<u64, TileError> {
+    create_dir_and_save_with(path, img, img_ops, &SolidTiles::default())
+}
+
+/// `create_dir_and_save`, remembering single colour images in `solid`
+fn create_dir_and_save_with(
+    path: impl AsRef<Path>,
+    img: &DynamicImage,
+    img_ops: &ImageOps,
+    solid: &SolidTiles,
+) -> Result<u64, TileError> {
+    let path = path.as_ref();
+    let mut data = Vec::new();
+    solid
+        .encode(img, img_ops, &mut data)
+        .with_context(|| anyhow!("encoding {path:?}"))?;
+    create_dir_and_write_bytes(path, &data)?;
+    Ok(data.len() as u64)
+}
+
+/// the encodings of a run's single colour tiles, e.g. of open sea, as the same few colours
+/// come up over and over
+#[derive(Default)]
+struct SolidTiles {
+    encoded: Mutex<HashMap<SolidKey, Vec<u8>>>,
+}
+
+/// everything a single colour image's encoding depends on
+#[derive(PartialEq, Eq, Hash)]
+struct SolidKey {
+    img_ops: ImageOps,
+    wh: (u32, u32),
+    color: ColorType,
+    pixel: Vec<u8>,
+}
+
+/// colours remembered at once; more than this is more likely a gradient than open sea
+const MAX_SOLID: usize = 4096;
+
+impl SolidTiles {
+    /// `encode`, but an image of a single colour is encoded at the fastest settings, and only
+    /// the first time that colour comes up
+    fn encode(
+        &self,
+        img: &DynamicImage,
+        img_ops: &ImageOps,
+        mut out: impl Write,
+    ) -> Result<(), TileError> {
+        let Some(pixel) = solid_colour(img) else {
+            return encode(img, img_ops, out);
+        };
+        // there's no detail for a slower encode to find
+        let img_ops = ImageOps {
+            speed: 10,
+            encode_threads: 1,
+            ..*img_ops
+        };
+        let key = SolidKey {
+            img_ops,
+            wh: (img.width(), img.height()),
+            color: img.color(),
+            pixel: pixel.to_vec(),
+        };
+        if let Some(data) = self.encoded.lock().expect("poisoned").get(&key) {
+            out.write_all(data)?;
+            return Ok(());
+        }
+        let mut data = Vec::new();
+        encode(img, &img_ops, &mut data)?;
+        out.write_all(&data)?;
+        let mut encoded = self.encoded.lock().expect("poisoned");
+        if encoded.len() >= MAX_SOLID {
+            encoded.clear();
+        }
+        encoded.insert(key, data);
+        Ok(())
+    }
+}
+
+/// the bytes of the pixel every pixel of `img` is, if they're all the same; usually gives up
+/// within the first row
+fn solid_colour(img: &DynamicImage) -> Option<&[u8]> {
+    let bytes = img.as_bytes();
+    let first = bytes.get(..usize::from(img.color().bytes_per_pixel()))?;
+    bytes
+        .chunks_exact(first.len())
+        .all(|pixel| pixel == first)
+        .then_some(first)
+}
+
+/// `create_dir_and_save` for an already encoded image
+fn create_dir_and_write_bytes(path: impl AsRef<Path>, data: &[u8]) -> Result<()> {
+    let path = path.as_ref();
+    fs::create_dir_all(
+        path.parent()
+            .ok_or_else(|| anyhow!("expected directory in path name, not {path:?}"))?,
+    )
+    .with_context(|| anyhow!("creating directories for {path:?}"))?;
+    let mut out = tempfile_fast::Sponge::new_for(path)
+        .with_context(|| anyhow!("creating temporary file for {path:?}"))?;
+    out.write_all(data)
+        .with_context(|| anyhow!("writing {path:?}"))?;
+    out.commit()
+        .with_context(|| anyhow!("replacing {path:?}"))?;
+    Ok(())
+}
+
+/// `create_dir_and_write_bytes`, trying again, after a growing pause, on errors which look
+/// like a struggling (e.g. network) filesystem, up to `retries` times
+fn write_with_retries(path: &Path, data: &[u8], retries: u32) -> Result<()> {
+    let mut attempt = 0;
+    loop {
+        match create_dir_and_write_bytes(path, data) {
+            Err(e) if attempt < retries && is_transient(&e) => {
+                let pause = Duration::from_millis(100 << attempt.min(10));
+                warn!("retrying in {pause:?} after failing to write {path:?}: {e:#}");
+                std::thread::sleep(pause);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// remove the tiles, and their compressed copies, in every zoom directory deeper than
+/// `max_zoom`, then the directories they leave empty; anything else is left, with a warning
+fn prune_zooms(layout: &TileLayout, max_zoom: u32, dry_run: bool) -> Result<()> {
+    let parent = layout.zoom_parent().ok_or_else(|| {
+        anyhow!(
+            "can't prune zoom levels, as the layout {:?} doesn't have a directory for each",
+            layout.template.0
+        )
+    })?;
+    let entries = match fs::read_dir(&parent) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).with_context(|| anyhow!("listing {parent:?}")),
+    };
+    let is_tile = |path: &Path| {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let ext = layout.ext;
+        [
+            format!(".{ext}"),
+            format!(".{ext}.gz"),
+            format!(".{ext}.br"),
+        ]
+        .iter()
+        .any(|tile| name.ends_with(tile.as_str()))
+    };
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        // exactly as the layout writes them, so e.g. `08` isn't ours
+        let zoom = name
+            .parse::<u32>()
+            .ok()
+            .filter(|zoom| zoom.to_string() == name);
+        let Some(zoom) = zoom.filter(|zoom| *zoom > max_zoom) else {
+            continue;
+        };
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        let dir = entry.path();
+        if dry_run {
+            info!("would prune zoom level {zoom}, at {dir:?}");
+            continue;
+        }
+        info!("pruning zoom level {zoom}, at {dir:?}");
+        let mut left = 0;
+        // deepest first, so each directory is emptied before it's removed
+        let mut dirs = vec![dir.clone()];
+        let mut stack = vec![dir];
+        while let Some(dir) = stack.pop() {
+            for entry in fs::read_dir(&dir).with_context(|| anyhow!("listing {dir:?}"))? {
+                let entry = entry?;
+                let path = entry.path();
+                if entry.file_type()?.is_dir() {
+                    dirs.push(path.clone());
+                    stack.push(path);
+                } else if is_tile(&path) {
+                    fs::remove_file(&path).with_context(|| anyhow!("removing {path:?}"))?;
+                } else {
+                    left += 1;
+                }
+            }
+        }
+        for dir in dirs.iter().rev() {
+            // not empty, so holding something which isn't a tile
+            let _ = fs::remove_dir(dir);
+        }
+        if left > 0 {
+            warn!("left {left} files which aren't tiles in zoom level {zoom}");
+        }
+    }
+    Ok(())
+}
+
+/// put the directory `new` where `output` is, then delete whatever was there; readers see one
+/// or the other, never a mix, though without an atomic swap there's a moment with neither
+fn replace_dir(new: &Path, output: &Path) -> Result<()> {
+    if !output.exists() {
+        return fs::rename(new, output).with_context(|| anyhow!("moving {new:?} to {output:?}"));
+    }
+    // either way, `old` ends up holding what was there
+    let old = if exchange(new, output)? {
+        new.to_path_buf()
+    } else {
+        let old = with_suffix(new, "old");
+        fs::rename(output, &old).with_context(|| anyhow!("moving {output:?} to {old:?}"))?;
+        fs::rename(new, output).with_context(|| anyhow!("moving {new:?} to {output:?}"))?;
+        old
+    };
+    fs::remove_dir_all(&old).with_context(|| anyhow!("removing the old pyramid at {old:?}"))
+}
+
+/// atomically swap two paths, or false if the os or filesystem can't
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+fn exchange(a: &Path, b: &Path) -> Result<bool> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = |p: &Path| CString::new(p.as_os_str().as_bytes()).context("nul in path");
+    let (ca, cb) = (c_path(a)?, c_path(b)?);
+    let ret = unsafe {
+        libc::renameat2(
+            libc::AT_FDCWD,
+            ca.as_ptr(),
+            libc::AT_FDCWD,
+            cb.as_ptr(),
+            libc::RENAME_EXCHANGE,
+        )
+    };
+    if ret == 0 {
+        return Ok(true);
+    }
+    let e = io::Error::last_os_error();
+    match e.raw_os_error() {
+        Some(libc::ENOSYS | libc::EINVAL) => Ok(false),
+        _ => Err(e).with_context(|| anyhow!("swapping {a:?} and {b:?}")),
+    }
+}
+
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+fn exchange(_a: &Path, _b: &Path) -> Result<bool> {
+    Ok(false)
+}
+
+/// bytes free, to anyone, on the filesystem holding `path`, or the nearest directory above it
+/// which exists; `None` if there's no telling
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)] // the field types vary between platforms
+fn free_space(path: &Path) -> Option<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let existing = path
+        .ancestors()
+        .find(|p| p.as_os_str().is_empty() || p.exists())?;
+    let existing = if existing.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        existing
+    };
+    let c_path = CString::new(existing.as_os_str().as_bytes()).ok()?;
+    let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stats) } != 0 {
+        return None;
+    }
+    Some((stats.f_bavail as u64).saturating_mul(stats.f_frsize as u64))
+}
+
+#[cfg(not(unix))]
+fn free_space(_path: &Path) -> Option<u64> {
+    None
+}
+
+fn is_transient(e: &anyhow::Error) -> bool {
+    e.chain()
+        .filter_map(|e| e.downcast_ref::<io::Error>())
+        .any(|e| {
+            matches!(
+                e.kind(),
+                io::ErrorKind::Interrupted | io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+            ) || is_transient_errno(e.raw_os_error())
+        })
+}
+
+#[cfg(unix)]
+fn is_transient_errno(errno: Option<i32>) -> bool {
+    matches!(errno, Some(libc::EIO | libc::ESTALE | libc::EBUSY))
+}
+
+#[cfg(not(unix))]
+fn is_transient_errno(_errno: Option<i32>) -> bool {
+    false
+}
+
+/// `img` as 8 or 16-bit rgb(a), which every encoder takes as raw bytes, or `None` if it
+/// already is; palettes are already expanded by the decoder, grey becomes rgb, and anything
+/// more exotic becomes rgba8
+fn working_space(img: &DynamicImage) -> Option<DynamicImage> {
+    Some(match img {
+        DynamicImage::ImageRgb8(_)
+        | DynamicImage::ImageRgba8(_)
+        | DynamicImage::ImageRgb16(_)
+        | DynamicImage::ImageRgba16(_) => return None,
+        DynamicImage::ImageLuma8(_) => DynamicImage::ImageRgb8(img.to_rgb8()),
+        DynamicImage::ImageLumaA8(_) => DynamicImage::ImageRgba8(img.to_rgba8()),
+        DynamicImage::ImageLuma16(_) => DynamicImage::ImageRgb16(img.to_rgb16()),
+        DynamicImage::ImageLumaA16(_) => DynamicImage::ImageRgba16(img.to_rgba16()),
+        _ => DynamicImage::ImageRgba8(img.to_rgba8()),
+    })
+}
+
+/// encode `img` with the configured format
+pub fn encode(img: &DynamicImage, img_ops: &ImageOps, out: impl Write) -> Result<(), TileError> {
+    let color = img.color();
+    let deep = color.bytes_per_pixel() > color.channel_count();
+    let keeps_depth = img_ops.format == OutputFormat::Png && !img_ops.flatten;
+    if deep && !keeps_depth && !img_ops.force_8bit {
+        return Err(anyhow!(
+            "the image has {} bits a channel, but {:?} tiles{} only hold 8; \
+             allow reducing it with --force-8bit",
+            color.bytes_per_pixel() / color.channel_count() * 8,
+            img_ops.format,
+            if img_ops.flatten { ", flattened," } else { "" }
+        )
+        .into());
+    }
+    let converted = if img_ops.format == OutputFormat::Jpeg || img_ops.flatten {
+        Some(DynamicImage::ImageRgb8(flatten(img, img_ops.background)))
+    } else {
+        working_space(img)
+    };
+    let img = converted.as_ref().unwrap_or(img);
+    encode_converted(img, img_ops, out).map_err(TileError::encoding)
+}
+
+/// `encode`, once the image is in a form the format takes
+fn encode_converted(img: &DynamicImage, img_ops: &ImageOps, mut out: impl Write) -> Result<()> {
+    match img_ops.format {
+        OutputFormat::Avif => {
+            let enc = AvifEncoder::new_with_speed_quality(&mut out, img_ops.speed, img_ops.quality)
+                .with_colorspace(img_ops.avif_colorspace.to_image())
+                .with_num_threads((img_ops.encode_threads > 0).then_some(img_ops.encode_threads));
+            enc.write_image(img.as_bytes(), img.width(), img.height(), img.color())?;
+        }
+        OutputFormat::Png => {
+            let enc = PngEncoder::new(&mut out);
+            enc.write_image(img.as_bytes(), img.width(), img.height(), img.color())?;
+        }
+        OutputFormat::Webp => {
+            let quality = if img_ops.lossless {
+                WebPQuality::lossless()
+            } else {
+                WebPQuality::lossy(img_ops.quality)
+            };
+            // the webp encoder only accepts 8-bit rgb(a)
+            let converted;
+            let img = match img.color() {
+                ColorType::Rgb8 | ColorType::Rgba8 => img,
+                _ => {
+                    converted = DynamicImage::ImageRgba8(img.to_rgba8());
+                    &converted
+                }
+            };
+            let enc = WebPEncoder::new_with_quality(&mut out, quality);
+            enc.write_image(img.as_bytes(), img.width(), img.height(), img.color())?;
+        }
+        OutputFormat::Jpeg => {
+            // already flattened
+            let enc = JpegEncoder::new_with_quality(&mut out, img_ops.quality);
+            enc.write_image(img.as_bytes(), img.width(), img.height(), img.color())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// small enough to run in a moment
+    fn small_png() -> TileOptions {
+        TileOptions {
+            base_size: 64,
+            tile_size: 32,
+            overview_res: 32,
+            img_ops: ImageOps {
+                format: OutputFormat::Png,
+                ..ImageOps::default()
+            },
+            ..TileOptions::default()
+        }
+    }
+
+    fn save_base(dir: &Path, name: &str, wh: u32) {
+        RgbaImage::from_pixel(wh, wh, Rgba([200, 100, 50, 255]))
+            .save(dir.join(name))
+            .unwrap();
+    }
+
+    #[test]
+    fn transparent_at_any_depth() {
+        assert!(is_entirely_transparent(&DynamicImage::new_rgba16(4, 4)));
+        assert!(is_entirely_transparent(&DynamicImage::new_luma_a8(4, 4)));
+        assert!(is_entirely_transparent(&DynamicImage::new_luma_a16(4, 4)));
+
+        let mut visible = DynamicImage::new_rgba16(4, 4);
+        visible
+            .as_mut_rgba16()
+            .unwrap()
+            .put_pixel(3, 3, Rgba([0, 0, 0, 1]));
+        assert!(!is_entirely_transparent(&visible));
+        assert!(is_region_transparent(&visible, 0, 0, 3, 3));
+    }
+
+    #[test]
+    fn no_alpha_is_never_transparent() {
+        assert!(!is_entirely_transparent(&DynamicImage::new_rgb8(4, 4)));
+    }
+
+    #[test]
+    fn semi_transparent_neighbours_meet_without_seams() {
+        let tile = |rgba| DynamicImage::ImageRgba8(RgbaImage::from_pixel(1, 1, Rgba(rgba)));
+        let children = HashMap::from([
+            ((0, 0), tile([255, 0, 0, 64])),
+            ((1, 0), tile([0, 0, 255, 192])),
+        ]);
+        let (composite, empty) = composite(&children, 1, (0, 0), 2);
+        assert!(!empty);
+        // copied exactly, either side of the boundary, and not blended with the canvas
+        let composite = composite.to_rgba8();
+        assert_eq!(composite.get_pixel(0, 0).0, [255, 0, 0, 64]);
+        assert_eq!(composite.get_pixel(1, 0).0, [0, 0, 255, 192]);
+        assert_eq!(composite.get_pixel(0, 1).0, [0, 0, 0, 0]);
+
+        // each colour counts by its alpha, and the transparent black below adds none
+        let shrunk = resize(
+            &DynamicImage::ImageRgba8(composite),
+            1,
+            FilterType::Triangle,
+            false,
+        );
+        let [r, g, b, a] = shrunk.to_rgba8().get_pixel(0, 0).0;
+        let near = |got: u8, want: u8| got.abs_diff(want) <= 1;
+        assert!(
+            near(r, 64) && g == 0 && near(b, 191) && near(a, 64),
+            "{:?}",
+            [r, g, b, a]
+        );
+    }
+
+    #[test]
+    fn bases_of_the_wrong_size() {
+        let input = tempfile::tempdir().unwrap();
+        save_base(input.path(), "a_0_0.png", 64);
+        save_base(input.path(), "a_1_0.png", 48);
+
+        let output = tempfile::tempdir().unwrap();
+        let e = tile_directory(input.path(), output.path(), &small_png()).unwrap_err();
+        let e = format!("{e:#}");
+        assert!(e.contains("should be 64x64 (see --base-size)"), "{e}");
+        assert!(e.contains("a_1_0.png\" is 48x48"), "{e}");
+
+        let output = tempfile::tempdir().unwrap();
+        let opts = TileOptions {
+            allow_mismatched: true,
+            ..small_png()
+        };
+        let stats = tile_directory(input.path(), output.path(), &opts).unwrap();
+        // the small one's dropped, as if it weren't there
+        assert_eq!(stats.bases, 1);
+        assert!(output.path().join("1/0/0.png").exists());
+        assert!(!output.path().join("1/1/0.png").exists());
+    }
+
+    #[test]
+    fn nothing_to_tile() {
+        let input = tempfile::tempdir().unwrap();
+        let output = tempfile::tempdir().unwrap();
+        let e = tile_directory(input.path(), output.path(), &small_png()).unwrap_err();
+        let e = format!("{e:#}");
+        assert!(e.contains("no input files in"), "{e}");
+        assert!(e.contains("matched the name pattern"), "{e}");
+    }
+
+    #[test]
+    fn palette_input_round_trips() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a_0_0.png");
+        let mut enc = png::Encoder::new(File::create(&path).unwrap(), 4, 4);
+        enc.set_color(png::ColorType::Indexed);
+        enc.set_depth(png::BitDepth::Eight);
+        enc.set_palette(vec![255, 0, 0, 0, 0, 255]);
+        let mut writer = enc.write_header().unwrap();
+        // red on the left, blue on the right
+        writer.write_image_data(&[0, 0, 1, 1].repeat(4)).unwrap();
+        writer.finish().unwrap();
+
+        let img = TileOptions::default().decode(&path).unwrap();
+        for format in [OutputFormat::Png, OutputFormat::Webp] {
+            let img_ops = ImageOps {
+                format,
+                lossless: true,
+                ..ImageOps::default()
+            };
+            let mut data = Vec::new();
+            encode(&img, &img_ops, &mut data).unwrap();
+            let back = image::load_from_memory(&data).unwrap().to_rgba8();
+            assert_eq!(back.dimensions(), (4, 4));
+            assert_eq!(back.get_pixel(0, 3).0, [255, 0, 0, 255], "{format:?}");
+            assert_eq!(back.get_pixel(3, 0).0, [0, 0, 255, 255], "{format:?}");
+        }
+    }
+
+    #[test]
+    fn tiles_outside_their_level() {
+        assert!(check_in_level(3, 7, 7).is_ok());
+        assert!(check_in_level(3, 8, 0).is_err());
+        assert!(check_in_level(3, 0, 8).is_err());
+        assert!(check_in_level(32, 0, 0).is_err());
+
+        // with the base's own tile placed at zoom 31, its detail would be at 32
+        let input = tempfile::tempdir().unwrap();
+        save_base(input.path(), "a_0_0.png", 64);
+        let output = tempfile::tempdir().unwrap();
+        let opts = TileOptions {
+            zoom_offset: 31,
+            tile_offset: TileOffset { x: 5, y: 3 },
+            ..small_png()
+        };
+        let e = tile_directory(input.path(), output.path(), &opts).unwrap_err();
+        assert!(
+            format!("{e:#}").contains("past the deepest possible"),
+            "{e:#}"
+        );
+        assert!(!output.path().join("32").exists());
+    }
+
+    #[test]
+    fn other_files_beside_the_bases() {
+        let input = tempfile::tempdir().unwrap();
+        save_base(input.path(), "a_0_0.png", 64);
+        fs::write(
+            input.path().join("a_1_0.txt"),
+            "notes about the tile to the right",
+        )
+        .unwrap();
+
+        let output = tempfile::tempdir().unwrap();
+        let stats = tile_directory(input.path(), output.path(), &small_png()).unwrap();
+        assert_eq!(stats.bases, 1);
+        assert!(output.path().join("1/0/0.png").exists());
+    }
+
+    #[test]
+    fn missing_input_is_a_mistake() {
+        let output = tempfile::tempdir().unwrap();
+        let output = output.path().join("out");
+        let missing = Path::new("/nonexistent/tiledir/input");
+        let e = tile_directory(missing, &output, &small_png()).unwrap_err();
+        assert!(matches!(e, TileError::Invalid(_)), "{e:?}");
+        assert!(!output.exists());
+    }
+
+    #[test]
+    fn dry_run_leaves_stale_staging() {
+        let input = tempfile::tempdir().unwrap();
+        save_base(input.path(), "a_0_0.png", 64);
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("out");
+        let staging = dir.path().join("out.tiledir-staging");
+        // as an interrupted run would leave it, bar that this one finished
+        tile_directory(input.path(), &staging, &small_png()).unwrap();
+
+        let opts = TileOptions {
+            staging: true,
+            dry_run: true,
+            ..small_png()
+        };
+        let stats = tile_directory(input.path(), &output, &opts).unwrap();
+        // nothing in the stale one would be kept
+        assert_eq!(stats.tiles, [1, 4]);
+        assert!(staging.join("1/1/1.png").exists());
+        assert!(!output.exists());
+    }
+
+    #[test]
+    fn solid_tiles_encoded_once() {
+        let solid = SolidTiles::default();
+        let img_ops = small_png().img_ops;
+        let sea = DynamicImage::ImageRgba8(RgbaImage::from_pixel(32, 32, Rgba([0, 50, 200, 255])));
+        let (mut first, mut again) = (Vec::new(), Vec::new());
+        solid.encode(&sea, &img_ops, &mut first).unwrap();
+        solid.encode(&sea, &img_ops, &mut again).unwrap();
+        assert_eq!(first, again);
+        assert_eq!(solid.encoded.lock().unwrap().len(), 1);
+
+        let mut coast = sea.to_rgba8();
+        coast.put_pixel(31, 31, Rgba([200, 180, 100, 255]));
+        let mut data = Vec::new();
+        solid
+            .encode(&DynamicImage::ImageRgba8(coast), &img_ops, &mut data)
+            .unwrap();
+        assert_eq!(solid.encoded.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn interrupted_by_a_flag_of_its_own() {
+        let input = tempfile::tempdir().unwrap();
+        save_base(input.path(), "a_0_0.png", 64);
+        let output = tempfile::tempdir().unwrap();
+        let opts = TileOptions {
+            interrupt: Some(Arc::new(AtomicBool::new(true))),
+            ..small_png()
+        };
+        let stats = tile_directory(input.path(), output.path(), &opts).unwrap();
+        assert!(stats.interrupted);
+        assert_eq!(stats.tiles.iter().sum::<usize>(), 0);
+    }
+
+    #[test]
+    fn tiles_running_off_a_small_base() {
+        let img = DynamicImage::new_rgba8(48, 48);
+        let base = Path::new("a_0_0.png");
+        assert!(base_square_fits(&img, base, (0, 0), 32, false).unwrap());
+
+        let e = base_square_fits(&img, base, (1, 1), 32, false).unwrap_err();
+        assert_eq!(e.to_string(), r#"tiling "a_0_0.png""#);
+        assert_eq!(
+            e.root_cause().to_string(),
+            "32px square at 32x32 is outside the 48x48 image"
+        );
+        // warned about, and left out
+        assert!(!base_square_fits(&img, base, (1, 1), 32, true).unwrap());
+        assert!(!base_square_fits(&img, base, (0, 1), 32, true).unwrap());
+    }
+
+    #[test]
+    fn verify_skips_the_shrunk_copies() {
+        let input = tempfile::tempdir().unwrap();
+        save_base(input.path(), "a_0_0.png", 64);
+        let output = tempfile::tempdir().unwrap();
+        let opts = small_png();
+        tile_directory(input.path(), output.path(), &opts).unwrap();
+        let hidden = output.path().join(".tiledir-shrunk/0_0.png");
+        assert!(hidden.exists());
+        fs::write(&hidden, b"not a tile").unwrap();
+        fs::write(output.path().join("0/0/0.png"), b"truncated").unwrap();
+
+        let bad = verify_tiles(output.path(), &opts).unwrap();
+        assert_eq!(
+            bad.iter().map(|(path, _)| path).collect_vec(),
+            [&output.path().join("0/0/0.png")]
+        );
+    }
+
+    #[test]
+    fn quadkeys() {
+        assert_eq!(quadkey(0, 0, 0), "");
+        assert_eq!(quadkey(1, 1, 0), "1");
+        assert_eq!(quadkey(1, 0, 1), "2");
+        assert_eq!(quadkey(3, 3, 5), "213");
+        // from the bing maps tile system docs
+        assert_eq!(quadkey(16, 35210, 21493), "1202102332221212");
+    }
+}
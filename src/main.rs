@@ -1,277 +1,1036 @@
 use anyhow::{anyhow, Context, Result};
-use std::collections::HashMap;
 use std::fs;
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::time::Instant;
-
-use clap::Parser;
-use image::codecs::avif::AvifEncoder;
-use image::imageops::FilterType;
-use image::DynamicImage;
-use image::ImageEncoder;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock};
+use std::time::{Duration, Instant};
+
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, ValueEnum};
+use image::{DynamicImage, ImageError};
 use itertools::Itertools;
-use log::{debug, info};
-use rand::prelude::*;
-use rayon::prelude::*;
+use log::{info, warn, LevelFilter};
 use regex::Regex;
+use serde::Deserialize;
+use tiledir::{
+    encode, tile_directory, verify_tiles, AvifColorSpace, Bounds, Event, EventSink, ImageOps,
+    InputFormat, Naming, OnDuplicate, Origin, OutputFormat, PathTemplate, Phase, Precompress,
+    ResizeFilter, Scheme, TileError, TileOffset, TileOptions, TileStats, DEFAULT_EXTENSIONS,
+    DEFAULT_NAME_PATTERN,
+};
+
+/// how log lines, and the summary, are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum LogFormat {
+    /// for people; the summary goes to stdout
+    Text,
+    /// one json object per line on stderr, with events for each phase and base, and the summary
+    Json,
+}
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
-    /// directory full of input images
-    input: PathBuf,
+    /// directories full of input images, searched along with their subdirectories, or single
+    /// input images, whose names must still match the name pattern; any mix of the two
+    #[clap(required_unless_present = "layer", conflicts_with = "layer")]
+    input: Vec<PathBuf>,
+
+    /// directory to write the tile pyramid into; created if it doesn't exist
+    #[clap(short, long, default_value = "out")]
+    output: PathBuf,
 
     /// avif speed; 0 (slowest) - 10 (fastest); meaning not clearly defined
     ///
     /// 6 -> ~1h30m, 8 -> ~30 minutes; 10 -> ~10 minutes
     ///
     /// 6 is the upstream default but I can't say I'd recommend waiting
-    #[clap(short, long, default_value = "8", value_parser = clap::value_parser!(u8).range(0..=10))]
+    ///
+    /// the flag wins over TILEDIR_SPEED, which wins over the default
+    #[clap(short, long, env = "TILEDIR_SPEED", default_value = "8", value_parser = clap::value_parser!(u8).range(0..=10))]
     speed: u8,
 
-    /// avif quality; 0 (terrible) - 100 (uselessly huge)
+    /// avif, webp or jpeg quality; 0 (terrible) - 100 (uselessly huge)
     ///
     /// 90 is very good, 70 is good, 60 is okay
-    #[clap(short, long, default_value = "70", value_parser = clap::value_parser!(u8).range(0..=100))]
-    quality: u8,
+    ///
+    /// a comma separated list gives the quality for each zoom level in turn, starting at 0,
+    /// with the last applying to every deeper level; e.g. 50,60,80
+    ///
+    /// the flag wins over TILEDIR_QUALITY, which wins over the default
+    #[clap(short, long, env = "TILEDIR_QUALITY", default_value = "70", value_delimiter = ',', value_parser = clap::value_parser!(u8).range(0..=100))]
+    quality: Vec<u8>,
+
+    /// inclusive range of base coordinates to tile, as LX,LY,RX,RY
+    ///
+    /// defaults to the smallest range covering every discovered file; relative to the origin
+    #[clap(long, allow_hyphen_values = true)]
+    bounds: Option<Bounds>,
+
+    /// width and height of each input image, in pixels
+    #[clap(long, default_value = "4096", value_parser = clap::value_parser!(u32).range(1..))]
+    base_size: u32,
+
+    /// width and height of each output tile, in pixels
+    ///
+    /// the base size must be a power-of-two multiple of this
+    #[clap(long, default_value = "256", value_parser = clap::value_parser!(u32).range(1..))]
+    tile_size: u32,
+
+    /// image format to write tiles in; speed only applies to avif, quality to everything but png
+    #[clap(short, long, value_enum, default_value_t = OutputFormat::Avif)]
+    format: OutputFormat,
+
+    /// write lossless webp, ignoring quality
+    #[clap(long)]
+    lossless: bool,
+
+    /// filter used when scaling images down; nearest keeps pixel art sharp
+    #[clap(long, value_enum, default_value_t = ResizeFilter::Lanczos3)]
+    filter: ResizeFilter,
+
+    /// regex matching input file names; groups named x and y, or else the first two capture
+    /// groups, are the coordinates
+    ///
+    /// with a group named z too, e.g. `_z(?<z>\d+)_x(?<x>\d+)_y(?<y>\d+)\.`, the inputs are
+    /// taken as tiles already, all at that zoom, and only the shallower levels are built from
+    /// them, plus that level again unless --max-zoom is below it; --base-size is then ignored
+    #[clap(long, default_value = DEFAULT_NAME_PATTERN, value_parser = parse_name_pattern)]
+    name_pattern: Regex,
+
+    /// tile numbering; xyz has y increasing downwards, tms has it increasing upwards
+    #[clap(long, value_enum, default_value_t = Scheme::Xyz)]
+    scheme: Scheme,
+
+    /// regenerate tiles even if they already exist
+    #[clap(long)]
+    force: bool,
+
+    /// number of threads to use; defaults to one per core
+    #[clap(short, long, value_parser = clap::value_parser!(u16).range(1..))]
+    jobs: Option<u16>,
+
+    /// don't draw progress bars, even on a terminal
+    #[clap(long)]
+    no_progress: bool,
+
+    /// report how many tiles would be written, without writing anything
+    ///
+    /// every input image still has to be decoded to check for transparency, so this isn't quick
+    #[clap(long)]
+    dry_run: bool,
+
+    /// write transparent tiles where there's no data, so viewers never see a missing tile
+    #[clap(long)]
+    fill_blank: bool,
+
+    /// shallowest zoom level to write; defaults to 0, a single tile covering everything
+    #[clap(long)]
+    min_zoom: Option<u32>,
+
+    /// deepest zoom level to write; defaults to where one tile pixel is one base pixel,
+    /// which is the deepest possible, so set a larger base or smaller tile size to go deeper
+    #[clap(long)]
+    max_zoom: Option<u32>,
+
+    /// what to do when two input files have the same coordinates
+    #[clap(long, value_enum, default_value_t = OnDuplicate::Error)]
+    on_duplicate: OnDuplicate,
+
+    /// colour space for avif tiles; bt709 (ycbcr) is smaller, srgb skips the conversion
+    ///
+    /// the encoder only takes 8-bit input, so deeper images are truncated either way
+    #[clap(long, value_enum, default_value_t = AvifColorSpace::Bt709)]
+    avif_colorspace: AvifColorSpace,
+
+    /// skip, with a warning, input images which aren't base size square, instead of failing
+    #[clap(long)]
+    allow_mismatched: bool,
+
+    /// delete the output directory before starting, so no tiles from earlier runs are left behind
+    #[clap(long)]
+    clean: bool,
+
+    /// don't ask before deleting anything
+    #[clap(long)]
+    yes: bool,
+
+    /// warn about input images which can't be read, and carry on without them, instead of failing
+    #[clap(long)]
+    skip_errors: bool,
+
+    /// also write an index.html showing the tiles on a leaflet map
+    ///
+    /// browsers won't load the tiles from a file:// url; serve the output directory instead,
+    /// e.g. with `python3 -m http.server`
+    #[clap(long)]
+    with_viewer: bool,
+
+    /// colour drawn behind partially transparent jpeg tiles, or any with --flatten, as hex rrggbb
+    #[clap(long, default_value = "ffffff", value_parser = parse_background)]
+    background: [u8; 3],
+
+    /// decode input images as this format, instead of guessing from their content and name
+    #[clap(long, value_enum)]
+    input_format: Option<InputFormat>,
+
+    /// hard link tiles identical to one already written, instead of writing them again
+    ///
+    /// copies are written instead on filesystems without hard links
+    #[clap(long)]
+    dedup: bool,
+
+    /// only log warnings and errors, whatever RUST_LOG says; there's no -q, as that's quality
+    #[clap(long, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// log more: once for progress, twice for each tile, thrice for everything
+    #[clap(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// scale in linear light rather than on the srgb values, so shrunk detail doesn't darken
+    ///
+    /// costs a conversion each way for every pixel scaled; the overview levels are the most visibly improved
+    #[clap(long)]
+    linear_resize: bool,
+
+    /// base coordinates to treat as 0,0, as X,Y; subtracted from every file name's coordinates
+    #[clap(long, allow_hyphen_values = true, default_value = "0,0")]
+    origin: Origin,
+
+    /// times to retry writing a tile after an io error which might be transient, e.g. on nfs
+    #[clap(long, default_value = "3")]
+    write_retries: u32,
+
+    /// only tile this many input images, picked at random, for a quick preview of the settings
+    ///
+    /// the rest are treated as missing, so the pyramid is complete but sparse. entirely
+    /// transparent images can be picked, as that isn't known until they're decoded
+    #[clap(long)]
+    sample: Option<usize>,
+
+    /// tile another directory, as NAME=DIR, into its own NAME subdirectory of the output
+    ///
+    /// repeat for more layers, which are done one after the other with the same settings;
+    /// instead of the input argument
+    #[clap(long, value_parser = parse_layer)]
+    layer: Vec<(String, PathBuf)>,
+
+    /// for debugging: write the shrunk copy of every base, and every overview tile before
+    /// it's scaled down, as png into this directory
+    #[clap(long)]
+    dump_intermediate: Option<PathBuf>,
+
+    /// fail, instead of warning, if any coordinate inside the bounds has no input file
+    #[clap(long)]
+    strict: bool,
+
+    /// path of each tile under the output, without the extension; needs {z}, {x} and {y} once each
+    #[clap(long, default_value = "{z}/{x}/{y}")]
+    layout: PathTemplate,
+
+    /// name each tile by its quadkey, as bing maps does, in a single directory, rather than
+    /// by --layout; zoom 0 has no quadkey, so is left out
+    #[clap(long, value_enum, default_value_t = Naming::Xyz, conflicts_with_all = ["layout", "scheme", "mbtiles", "prune_zooms"])]
+    naming: Naming,
+
+    /// only write the overview zoom levels, those covering more than one base, leaving the
+    /// detailed tiles alone; every base is still decoded, to build the overview from
+    #[clap(long, conflicts_with = "only_detail")]
+    only_overview: bool,
+
+    /// only write the detailed zoom levels, cut straight from each base, leaving the overview alone
+    #[clap(long)]
+    only_detail: bool,
+
+    /// afterwards, read back every tile in the output and fail if any don't decode
+    ///
+    /// avif tiles only have their container checked, which catches truncation
+    #[clap(long, conflicts_with = "dry_run")]
+    verify: bool,
+
+    /// most input images to have decoded at once, to cap memory use; defaults to one per thread
+    ///
+    /// each 4096px base takes 64MB while it's tiled, and counts twice with --bleed, for the
+    /// neighbour decoded beside it
+    #[clap(long, value_parser = clap::value_parser!(u16).range(1..))]
+    max_in_flight: Option<u16>,
+
+    /// write the tiles into this mbtiles (sqlite) database, instead of as files in the output
+    ///
+    /// rows are always numbered bottom up, as mbtiles requires, whatever --scheme says
+    #[clap(long, conflicts_with_all = ["layer", "dedup", "with_viewer", "verify", "layout", "clean", "precompress"])]
+    mbtiles: Option<PathBuf>,
+
+    /// text for people, or json lines on stderr for other programs; json hides the progress bars
+    #[clap(long, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+
+    /// only remake the bases inside LX,LY,RX,RY, and the overview tiles covering them; same
+    /// coordinates as --bounds
+    ///
+    /// the rest of the overview comes from shrunk copies left by earlier runs, where they're newer
+    /// than the input image, so only the region's images need decoding
+    #[clap(long, allow_hyphen_values = true)]
+    region: Option<Bounds>,
+
+    /// width each input image is shrunk to before the overview zoom levels are built from them
+    ///
+    /// up to the tile size makes the overview sharper, but every copy is held in memory
+    /// at once, four bytes a pixel
+    #[clap(long, default_value_t = 256)]
+    overview_res: u32,
+
+    /// csv of name,x,y lines giving each input file's coordinates, instead of reading them
+    /// from the file names; names are relative to the input directory
+    #[clap(long = "manifest", conflicts_with = "name_pattern")]
+    coordinates: Option<PathBuf>,
+
+    /// scale each detailed tile with this many pixels of context around it, from the
+    /// neighbouring input images at their edges, to hide seams; decodes every image's
+    /// neighbours too, so is slower
+    #[clap(long, default_value_t = 0)]
+    bleed: u32,
+
+    /// draw avif, png and webp tiles over --background too, as jpeg tiles always are, leaving
+    /// them opaque
+    #[clap(long)]
+    flatten: bool,
+
+    /// write a rough preview of zoom levels 0 - 2 before decoding anything, from the shrunk
+    /// copies earlier runs left, or grey squares where there are none; replaced at the end
+    #[clap(long)]
+    fast_overview: bool,
+
+    /// reduce images with more than 8 bits a channel to 8 for avif, webp, jpeg or flattened
+    /// tiles, instead of refusing to; png tiles otherwise keep all 16
+    #[clap(long)]
+    force_8bit: bool,
+
+    /// avif speed for the overview zoom levels, which are few, instead of --speed
+    #[clap(long, value_parser = clap::value_parser!(u8).range(0..=10))]
+    overview_speed: Option<u8>,
+
+    /// avif speed for the detailed zoom levels, which are most of the tiles, instead of --speed
+    #[clap(long, value_parser = clap::value_parser!(u8).range(0..=10))]
+    detail_speed: Option<u8>,
+
+    /// filter for shrinking the input images and building the overview zoom levels from them,
+    /// instead of --filter; e.g. triangle or nearest keeps small text from smearing
+    #[clap(long, value_enum)]
+    overview_filter: Option<ResizeFilter>,
+
+    /// filter for the detailed zoom levels, cut straight from each input image, instead of --filter
+    #[clap(long, value_enum)]
+    detail_filter: Option<ResizeFilter>,
+
+    /// also write a compressed copy beside each tile, tilejson.json and index.html, as
+    /// .gz or .br, for a server to send instead of compressing every response
+    ///
+    /// avif, webp and jpeg tiles are already compressed, so barely shrink; png tiles and
+    /// the metadata are what gain
+    #[clap(long, value_enum, default_value_t = Precompress::None)]
+    precompress: Precompress,
+
+    /// read settings from this toml file, for any of bounds, base-size, tile-size, quality,
+    /// speed, format, filter and output not given as flags or environment variables
+    ///
+    /// keys are named as the flags, e.g. `base-size = 8192`; bounds is a string like the flag,
+    /// and quality a number or a list of them
+    #[clap(long)]
+    config: Option<PathBuf>,
+
+    /// comma separated extensions of the files to take as inputs; others whose names match
+    /// are skipped with a warning, rather than failing to decode. * takes anything
+    #[clap(long, default_value = DEFAULT_EXTENSIONS, value_delimiter = ',')]
+    extensions: Vec<String>,
+
+    /// keep each existing detailed tile if it's newer than its input image, and remake it if
+    /// it's older, as make would, ignoring how it was made; by default the record of how each
+    /// tile was made decides, which also catches changed settings
+    #[clap(long, conflicts_with_all = ["force", "mbtiles"])]
+    overwrite_newer: bool,
+
+    /// threads each avif tile's encoder may use, on top of the --jobs tiling in parallel;
+    /// 0 lets each use all of them
+    ///
+    /// there's a tile per thread already, so more than 1 mostly oversubscribes the cores,
+    /// unless there are very few tiles left to do
+    #[clap(long, alias = "threads-per-encode", default_value_t = 1)]
+    encode_threads: usize,
+
+    /// skip tiles whose every pixel has an alpha up to this, out of 255, as if transparent,
+    /// e.g. 3 for the faint halos around anti-aliased captures
+    ///
+    /// anything drawn that faintly is lost with them, so keep it low
+    #[clap(long, default_value_t = 0, value_parser = clap::value_parser!(u8).range(0..=254))]
+    transparent_threshold: u8,
+
+    /// write a png here with a pixel per input image in the bounds, for checking a capture is
+    /// complete: green where there's something, grey where it's transparent, red where it's missing
+    ///
+    /// not written on a dry run, or when interrupted
+    #[clap(long, conflicts_with = "layer")]
+    coverage_map: Option<PathBuf>,
+
+    /// take input images smaller than the base size, e.g. partial ones at the edge of a
+    /// capture, as the top left of a transparent base; larger ones are still an error
+    #[clap(long)]
+    pad_partial: bool,
+
+    /// make the whole pyramid in a new directory beside the output, then swap it in once
+    /// it's finished, so a server never sees a mix of old and new tiles
+    ///
+    /// nothing from earlier runs is reused, so every tile is made again, and an interrupted
+    /// run can't be carried on
+    #[clap(long, conflicts_with_all = ["force", "region", "overwrite_newer", "mbtiles", "clean"])]
+    staging: bool,
+
+    /// also write every tile at twice the size, as {y}@2x beside it, for high dpi screens;
+    /// that's about three times the encoding, and the deepest level's are only scaled up
+    #[clap(long, conflicts_with_all = ["layer", "mbtiles"])]
+    retina: bool,
+
+    /// remove the tiles of zoom levels deeper than --max-zoom left by earlier runs, and the
+    /// directories they were in; only lists them with --dry-run
+    ///
+    /// files in there which aren't tiles are left alone
+    #[clap(long, conflicts_with_all = ["layer", "mbtiles"])]
+    prune_zooms: bool,
+
+    /// stop starting new tiles once the run has taken this long, e.g. 90s, 10m or 2h, and
+    /// exit successfully with what's done; the next run carries on from there
+    ///
+    /// the overview is only made once every base is done, so it's missing until then
+    #[clap(long, value_parser = parse_duration)]
+    time_budget: Option<Duration>,
+
+    /// most memory to let decoding one input image take, e.g. 2G or 512M, failing that image
+    /// instead; the default is 512M
+    #[clap(long, value_parser = parse_bytes)]
+    max_decode_bytes: Option<u64>,
+
+    /// widest, and tallest, input image to decode, in pixels, failing any bigger
+    #[clap(long)]
+    max_decode_dimensions: Option<u32>,
+
+    /// number the zoom levels written from this, not 0, so the pyramid can be put into a
+    /// bigger one; --min-zoom and --max-zoom still count from 0
+    #[clap(long, default_value_t = 0)]
+    zoom_offset: u32,
+
+    /// put the top tile at X,Y in the --zoom-offset level of the bigger pyramid, and every
+    /// deeper tile with it, e.g. to merge separately tiled regions into one directory
+    ///
+    /// unlike --origin, which moves the input coordinates, this moves the output tiles
+    #[clap(long, default_value = "0,0")]
+    tile_offset: TileOffset,
+
+    /// keep a json file of each tile's mean colour, as {"z/x/y": [r, g, b, a]}, for a viewer
+    /// to show while the tile loads; tiles from earlier runs keep their entries
+    #[clap(long, conflicts_with = "layer")]
+    color_index: Option<PathBuf>,
+
+    /// encode every tile with an input under it, even if it's entirely transparent, rather
+    /// than skipping it; for checking whether real content is being mistaken for nothing
+    #[clap(long, conflicts_with = "transparent_threshold")]
+    no_skip_transparent: bool,
+
+    /// don't estimate, before starting, whether the tiles will fit on the output's disk
+    ///
+    /// the estimate comes from encoding a tile from each of a few bases, so is rough
+    #[clap(long)]
+    skip_space_check: bool,
 }
 
-#[derive(Debug, Clone, Copy)]
-struct ImageOps {
-    quality: u8,
-    speed: u8,
+/// the settings a --config file can give
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct Config {
+    bounds: Option<String>,
+    base_size: Option<u32>,
+    tile_size: Option<u32>,
+    quality: Option<ConfigQuality>,
+    speed: Option<u8>,
+    format: Option<String>,
+    filter: Option<String>,
+    output: Option<PathBuf>,
 }
 
-fn main() -> Result<()> {
-    pretty_env_logger::init_timed();
-    let format = Regex::new(r".*_(-?\d+)_(-?\d+)\.")?;
-    let args: Cli = Cli::parse();
+/// one quality for everything, or one per zoom level, as with the flag
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ConfigQuality {
+    One(u8),
+    ByZoom(Vec<u8>),
+}
 
-    let img_ops = ImageOps {
-        quality: args.quality,
-        speed: args.speed,
+/// fill in anything `args` didn't get from a flag or the environment from the config file
+fn apply_config(args: &mut Cli, matches: &ArgMatches, path: &Path) -> Result<()> {
+    let text = fs::read_to_string(path).with_context(|| anyhow!("reading {path:?}"))?;
+    let config: Config = toml::from_str(&text).with_context(|| anyhow!("parsing {path:?}"))?;
+    let unset = |id: &str| {
+        matches!(
+            matches.value_source(id),
+            None | Some(ValueSource::DefaultValue)
+        )
     };
+    let bad = |key: &str, e: &dyn std::fmt::Display| anyhow!("bad {key} in {path:?}: {e}");
 
-    let base_wh = 4096u32;
-    let tile_wh = 256u32;
+    if let Some(bounds) = config.bounds.filter(|_| unset("bounds")) {
+        args.bounds = Some(bounds.parse().map_err(|e: TileError| bad("bounds", &e))?);
+    }
+    if let Some(base_size) = config.base_size.filter(|_| unset("base_size")) {
+        if base_size == 0 {
+            return Err(bad("base-size", &"must be at least 1"));
+        }
+        args.base_size = base_size;
+    }
+    if let Some(tile_size) = config.tile_size.filter(|_| unset("tile_size")) {
+        if tile_size == 0 {
+            return Err(bad("tile-size", &"must be at least 1"));
+        }
+        args.tile_size = tile_size;
+    }
+    if let Some(quality) = config.quality.filter(|_| unset("quality")) {
+        let quality = match quality {
+            ConfigQuality::One(quality) => vec![quality],
+            ConfigQuality::ByZoom(list) => list,
+        };
+        if quality.is_empty() || quality.iter().any(|q| *q > 100) {
+            return Err(bad(
+                "quality",
+                &"must be 0 - 100, or a non-empty list of those",
+            ));
+        }
+        args.quality = quality;
+    }
+    if let Some(speed) = config.speed.filter(|_| unset("speed")) {
+        if speed > 10 {
+            return Err(bad("speed", &"must be 0 - 10"));
+        }
+        args.speed = speed;
+    }
+    if let Some(format) = config.format.filter(|_| unset("format")) {
+        args.format =
+            <OutputFormat as ValueEnum>::from_str(&format, true).map_err(|e| bad("format", &e))?;
+    }
+    if let Some(filter) = config.filter.filter(|_| unset("filter")) {
+        args.filter =
+            <ResizeFilter as ValueEnum>::from_str(&filter, true).map_err(|e| bad("filter", &e))?;
+    }
+    if let Some(output) = config.output.filter(|_| unset("output")) {
+        args.output = output;
+    }
+    Ok(())
+}
 
-    let tile_per_base = base_wh / tile_wh; // 16
+fn parse_name_pattern(s: &str) -> Result<Regex> {
+    let format = Regex::new(s).with_context(|| anyhow!("compiling name pattern {s:?}"))?;
+    // includes the implicit whole-match group
+    if format.captures_len() < 3 {
+        return Err(anyhow!(
+            "name pattern needs capture groups for x and y, not {s:?}"
+        ));
+    }
+    Ok(format)
+}
 
-    info!("discovering files...");
-    let mut bases = Vec::new();
-    for entry in fs::read_dir(args.input)? {
-        let entry = entry?;
-        let path = entry.path();
-        let Some(file_name) = path.file_name() else {
-            continue;
-        };
-        let file_name = file_name
-            .to_str()
-            .ok_or_else(|| anyhow!("unrepresentable filename: {file_name:?}"))?;
-        let Some(captures) = format.captures(file_name) else {
-            continue;
-        };
-        let x = captures
-            .get(1)
-            .ok_or(anyhow!("missing capture group 1"))?
-            .as_str()
-            .parse::<i64>()?;
-        let y = captures
-            .get(2)
-            .ok_or(anyhow!("missing capture group 2"))?
-            .as_str()
-            .parse::<i64>()?;
-        bases.push((x, y, entry.path()));
-    }
-
-    // let lx = bases.iter().map(|(x, _, _)| *x).min().expect("non-empty");
-    // let ly = bases.iter().map(|(_, y, _)| *y).min().expect("non-empty");
-    // let rx = bases.iter().map(|(x, _, _)| *x).max().expect("non-empty");
-    // let ry = bases.iter().map(|(_, y, _)| *y).max().expect("non-empty");
-    let lx = -15;
-    let ly = -15;
-    let rx = 16;
-    let ry = 16;
-
-    let bw = u32::try_from(rx - lx)?;
-    let bh = u32::try_from(ry - ly)?;
-
-    info!("files available from {lx}x{ly} -> {rx}x{ry} ({bw}x{bh})");
-
-    let base_lookup = bases
-        .into_iter()
-        .map(|(x, y, path)| ((x, y), path))
-        .collect::<HashMap<_, _>>();
+fn parse_duration(s: &str) -> Result<Duration> {
+    let (number, unit) = s.split_at(s.trim_end_matches(char::is_alphabetic).len());
+    let secs = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        _ => return Err(anyhow!("expected a time like 90s, 10m or 2h, not {s:?}")),
+    };
+    let number = number
+        .parse::<u64>()
+        .with_context(|| anyhow!("expected a time like 90s, 10m or 2h, not {s:?}"))?;
+    Ok(Duration::from_secs(number * secs))
+}
 
-    let mut xys = (0..bw)
-        .flat_map(|x| (0..bh).map(move |y| (x, y)))
-        .collect_vec();
+fn parse_bytes(s: &str) -> Result<u64> {
+    let (number, unit) = s.split_at(s.trim_end_matches(char::is_alphabetic).len());
+    let shift = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 0,
+        "K" | "KB" => 10,
+        "M" | "MB" => 20,
+        "G" | "GB" => 30,
+        _ => return Err(anyhow!("expected a size like 512M or 2G, not {s:?}")),
+    };
+    let number = number
+        .parse::<u64>()
+        .with_context(|| anyhow!("expected a size like 512M or 2G, not {s:?}"))?;
+    number
+        .checked_mul(1 << shift)
+        .ok_or_else(|| anyhow!("{s:?} is too big"))
+}
 
-    // try not to process all the empty tiles at the same time
-    // (note that rayon already has a weird execution order)
-    xys.shuffle(&mut thread_rng());
+fn parse_background(s: &str) -> Result<[u8; 3]> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    let channel = |i: usize| {
+        hex.get(i..i + 2)
+            .and_then(|c| u8::from_str_radix(c, 16).ok())
+            .ok_or_else(|| anyhow!("expected a colour like ff8800, not {s:?}"))
+    };
+    if hex.len() != 6 {
+        return Err(anyhow!("expected a colour like ff8800, not {s:?}"));
+    }
+    Ok([channel(0)?, channel(2)?, channel(4)?])
+}
 
-    let shrunk_res = 256;
+fn parse_layer(s: &str) -> Result<(String, PathBuf)> {
+    let (name, dir) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow!("expected NAME=DIR, not {s:?}"))?;
+    let single = Path::new(name).components().count() == 1;
+    if name.is_empty() || name == "." || name == ".." || !single {
+        return Err(anyhow!(
+            "layer name should be a plain directory name, not {name:?}"
+        ));
+    }
+    Ok((name.to_string(), PathBuf::from(dir)))
+}
 
-    info!(
-        "loading {} (shrunk) images for lower zoom levels...",
-        xys.len()
+fn clean(inputs: &[&Path], output: &Path, yes: bool) -> Result<()> {
+    if !output.exists() {
+        return Ok(());
+    }
+    let output = output
+        .canonicalize()
+        .with_context(|| anyhow!("resolving {output:?}"))?;
+    for input in inputs {
+        let input = input
+            .canonicalize()
+            .with_context(|| anyhow!("resolving {input:?}"))?;
+        if input.starts_with(&output) {
+            return Err(anyhow!(
+                "refusing to clean {output:?}, as the input {input:?} is inside it"
+            ));
+        }
+    }
+    if !yes {
+        eprint!("delete {output:?} and everything in it? [y/N] ");
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer)?;
+        if !answer.trim().eq_ignore_ascii_case("y") {
+            return Err(anyhow!(
+                "not cleaning {output:?}; pass --yes to skip the question"
+            ));
+        }
+    }
+    info!("removing {output:?}");
+    fs::remove_dir_all(&output).with_context(|| anyhow!("removing {output:?}"))
+}
+
+fn print_summary(stats: &TileStats, dry_run: bool, dedup: bool) {
+    println!(
+        "{} images found, {} non-empty",
+        stats.bases, stats.non_blank
+    );
+    println!("zoom      tiles        bytes   manip s    save s");
+    for (zoom, (tiles, bytes)) in stats.tiles.iter().zip(&stats.bytes).enumerate() {
+        let manip = stats.manip_time[zoom].as_secs_f64();
+        let save = stats.save_time[zoom].as_secs_f64();
+        println!("{zoom:>4} {tiles:>10} {bytes:>12} {manip:>9.1} {save:>9.1}");
+    }
+    println!(
+        " all {:>10} {:>12} {:>9.1} {:>9.1}",
+        stats.tiles.iter().sum::<usize>(),
+        stats.bytes.iter().sum::<u64>(),
+        stats.manip_time.iter().sum::<Duration>().as_secs_f64(),
+        stats.save_time.iter().sum::<Duration>().as_secs_f64(),
+    );
+    println!(
+        "{} tiles skipped as transparent{}",
+        stats.transparent_tiles,
+        if dry_run {
+            "; dry run, nothing written"
+        } else {
+            ""
+        }
     );
 
-    let complete = AtomicUsize::new(0);
+    if dedup {
+        println!("{} tiles linked to an identical tile", stats.deduplicated);
+    }
+    if stats.unreadable > 0 {
+        println!("{} unreadable images skipped", stats.unreadable);
+    }
+    if stats.interrupted {
+        println!(
+            "stopped with {} of {} bases done, so the overview wasn't made; run again to carry on",
+            stats.bases_done, stats.bases
+        );
+    }
+}
 
-    let shrunk = xys
-        .par_iter()
-        .map(|(x, y)| -> Result<Option<((u32, u32), DynamicImage)>> {
-            let Some(base) = base_lookup.get(&(i64::from(*x) + lx, i64::from(*y) + ly)) else {
-                return Ok(None);
-            };
+/// `s` as a json string, quotes included
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if u32::from(c) < 0x20 => out.push_str(&format!("\\u{:04x}", u32::from(c))),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
 
-            let img = image::open(base).with_context(|| anyhow!("reading {base:?} for shrunk"))?;
-            if is_entirely_transparent(&img) {
-                return Ok(None);
-            }
+fn print_event(event: &Event) {
+    let line = match event {
+        Event::PhaseStart(phase) => format!(r#"{{"event":"phase_start","phase":"{phase}"}}"#),
+        Event::PhaseEnd(phase, elapsed) => format!(
+            r#"{{"event":"phase_end","phase":"{phase}","secs":{}}}"#,
+            elapsed.as_secs_f64()
+        ),
+        Event::Planned { tiles } => format!(r#"{{"event":"planned","tiles":{tiles}}}"#),
+        Event::Base {
+            path,
+            x,
+            y,
+            manip,
+            save,
+        } => format!(
+            r#"{{"event":"base","path":{},"x":{x},"y":{y},"manip_secs":{},"save_secs":{}}}"#,
+            json_string(&path.to_string_lossy()),
+            manip.as_secs_f64(),
+            save.as_secs_f64()
+        ),
+    };
+    eprintln!("{line}");
+}
+
+/// `print_summary`, as one json line on stderr
+fn print_summary_json(stats: &TileStats, layer: Option<&String>) {
+    let secs = |times: &[Duration]| times.iter().map(Duration::as_secs_f64).join(",");
+    eprintln!(
+        r#"{{"event":"summary","layer":{},"bases":{},"non_blank":{},"tiles":[{}],"bytes":[{}],"manip_secs":[{}],"save_secs":[{}],"transparent_tiles":{},"unreadable":{},"deduplicated":{},"interrupted":{},"bases_done":{}}}"#,
+        layer.map_or("null".to_string(), |name| json_string(name)),
+        stats.bases,
+        stats.non_blank,
+        stats.tiles.iter().join(","),
+        stats.bytes.iter().join(","),
+        secs(&stats.manip_time),
+        secs(&stats.save_time),
+        stats.transparent_tiles,
+        stats.unreadable,
+        stats.deduplicated,
+        stats.interrupted,
+        stats.bases_done,
+    );
+}
+
+/// the asked for format, unless it's avif and this build can't encode that, in which case webp;
+/// checked up front so a run never mixes formats
+fn usable_format(img_ops: &ImageOps) -> OutputFormat {
+    if img_ops.format != OutputFormat::Avif {
+        return img_ops.format;
+    }
+    match encode(&DynamicImage::new_rgba8(8, 8), img_ops, io::sink()) {
+        Err(TileError::Encode(e))
+            if matches!(e.downcast_ref(), Some(ImageError::Unsupported(_))) =>
+        {
+            warn!("can't encode avif here ({e:#}), so writing webp instead");
+            OutputFormat::Webp
+        }
+        _ => img_ops.format,
+    }
+}
+
+/// roughly how long avif tiles take at `speed`, relative to the default of 8; from the
+/// timings in --speed's help, 6 taking three times as long and 10 a third, extended downwards
+fn avif_slowness(speed: u8) -> f64 {
+    3f64.sqrt().powi(8 - i32::from(speed))
+}
+
+/// roughly how long a 256px avif tile takes, on one thread, at the default speed of 8
+const AVIF_TILE_SECS: f64 = 0.05;
 
-            Ok(Some((
-                (*x, *y),
-                img.resize(shrunk_res, shrunk_res, FilterType::Lanczos3),
-            )))
-        })
-        .collect::<Result<Vec<_>>>()?
+/// warn, without stopping, about settings which are almost certainly a mistake, for a run of
+/// about `tiles` tiles
+fn warn_about_settings(args: &Cli, tiles: u64) {
+    let speeds = [args.overview_speed, args.detail_speed]
         .into_iter()
         .flatten()
-        .collect::<HashMap<_, _>>();
+        .chain([args.speed])
+        .collect_vec();
+    let slowest = *speeds.iter().min().expect("always has --speed");
+    let fastest = *speeds.iter().max().expect("always has --speed");
+    let highest = *args.quality.iter().max().expect("has a default");
+    let lowest = *args.quality.iter().min().expect("has a default");
+    let avif = args.format == OutputFormat::Avif;
+    // png, and lossless webp, ignore the quality
+    let lossy = args.format != OutputFormat::Png && !args.lossless;
 
-    assert_eq!(bw, bh);
-    let mega_res = (bw + 1) * shrunk_res;
+    if avif && slowest <= 4 {
+        let threads = rayon::current_num_threads();
+        let area = (f64::from(args.tile_size) / 256.).powi(2);
+        let secs = AVIF_TILE_SECS * area * avif_slowness(slowest) * tiles as f64 / threads as f64;
+        let took = match secs / 3600. {
+            hours if hours >= 1. => format!("{hours:.1} hours"),
+            _ => format!("{:.0} minutes", secs / 60.),
+        };
+        warn!(
+            "avif at speed {slowest} is about {:.0} times as slow as the default of 8; \
+             the {tiles} tiles look like taking around {took} on {threads} threads",
+            avif_slowness(slowest)
+        );
+    }
+    if lossy && highest >= 98 {
+        warn!("quality {highest} makes huge tiles for no visible gain; 90 is already very good");
+    }
+    if avif && fastest >= 9 && lowest <= 20 {
+        warn!(
+            "avif at speed {fastest} and quality {lowest} makes garbage tiles; \
+             60 is about as low as looks okay, and 8 is fast enough"
+        );
+    } else if lossy && lowest <= 20 {
+        warn!("quality {lowest} makes very blocky tiles; 60 is about as low as looks okay");
+    }
+}
 
-    let total_non_blank = shrunk.len();
-    info!(
-        "{total_non_blank} (shrunk) images are non-empty, compositing into a {mega_res}² image..."
-    );
+/// warns about the settings once the run's size is known, and passes the event on to the
+/// json log, if that's what's wanted
+fn on_event(args: &Cli, event: &Event) {
+    if let Event::Planned { tiles } = event {
+        warn_about_settings(args, *tiles);
+    }
+    if args.log_format == LogFormat::Json {
+        print_event(event);
+    }
+}
 
-    let mut mega = DynamicImage::new_rgba8(mega_res, mega_res);
-    for y in 0..bh {
-        for x in 0..bw {
-            let Some(img) = shrunk.get(&(x, y)) else {
-                continue;
-            };
-            image::imageops::overlay(
-                &mut mega,
-                img,
-                i64::from(x * shrunk_res),
-                i64::from(y * shrunk_res),
-            );
-        }
+/// set by ctrl-c
+static INTERRUPTED: LazyLock<Arc<AtomicBool>> = LazyLock::new(Arc::default);
+
+/// the first ctrl-c asks the run to stop at the next tile; a second kills it as usual
+#[cfg(unix)]
+fn catch_interrupt() {
+    extern "C" fn handler(_: libc::c_int) {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+        unsafe { libc::signal(libc::SIGINT, libc::SIG_DFL) };
     }
+    // here, so the handler never has to make it
+    LazyLock::force(&INTERRUPTED);
+    unsafe {
+        libc::signal(
+            libc::SIGINT,
+            handler as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        )
+    };
+}
 
-    drop(shrunk);
+#[cfg(not(unix))]
+fn catch_interrupt() {}
 
-    assert_eq!(mega.width(), mega.height());
+fn main() -> Result<()> {
+    let matches = Cli::command().get_matches();
+    let mut args = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    // the flags win over the file, which wins over the defaults
+    if let Some(config) = args.config.clone() {
+        apply_config(&mut args, &matches, &config)?;
+    }
 
-    info!("slicing mega image into initial zoom levels...");
+    // as pretty_env_logger::init_timed, but with the flags overriding RUST_LOG's default level
+    let mut logger = pretty_env_logger::formatted_timed_builder();
+    if let Ok(filters) = std::env::var("RUST_LOG") {
+        logger.parse_filters(&filters);
+    }
+    let level = match (args.quiet, args.verbose) {
+        (true, _) => Some(LevelFilter::Warn),
+        (false, 0) => None,
+        (false, 1) => Some(LevelFilter::Info),
+        (false, 2) => Some(LevelFilter::Debug),
+        (false, _) => Some(LevelFilter::Trace),
+    };
+    if let Some(level) = level {
+        logger.filter_level(level);
+    }
+    if args.log_format == LogFormat::Json {
+        logger.format(|buf, record| {
+            writeln!(
+                buf,
+                r#"{{"event":"log","time":"{}","level":"{}","target":{},"message":{}}}"#,
+                buf.timestamp(),
+                record.level(),
+                json_string(record.target()),
+                json_string(&record.args().to_string())
+            )
+        });
+    }
+    logger.init();
 
-    (0..=4).into_par_iter().try_for_each(|zoom| -> Result<()> {
-        let mul = 2u32.pow(zoom);
-        let crop_wh = mega.width() / mul;
-        for y in 0..mul {
-            for x in 0..mul {
-                create_dir_and_save(
-                    format!("out/{zoom}/{x}/{y}.avif"),
-                    &mega
-                        .crop_imm(x * crop_wh, y * crop_wh, crop_wh, crop_wh)
-                        .resize(tile_wh, tile_wh, FilterType::Lanczos3),
-                    &img_ops,
-                )?;
-            }
+    let args = Arc::new(args);
+
+    let mut opts = TileOptions {
+        img_ops: ImageOps {
+            // overridden per zoom by the whole list
+            quality: *args.quality.last().expect("has a default"),
+            speed: args.speed,
+            format: args.format,
+            lossless: args.lossless,
+            avif_colorspace: args.avif_colorspace,
+            background: args.background,
+            flatten: args.flatten,
+            force_8bit: args.force_8bit,
+            encode_threads: args.encode_threads,
+        },
+        filter: args.filter,
+        name_pattern: args.name_pattern.clone(),
+        scheme: args.scheme,
+        layout: args.layout.clone(),
+        naming: args.naming,
+        origin: args.origin,
+        bounds: args.bounds,
+        base_size: args.base_size,
+        tile_size: args.tile_size,
+        force: args.force,
+        // the bars would garble the json on stderr
+        progress: !args.no_progress && args.log_format == LogFormat::Text,
+        dry_run: args.dry_run,
+        fill_blank: args.fill_blank,
+        min_zoom: args.min_zoom,
+        max_zoom: args.max_zoom,
+        on_duplicate: args.on_duplicate,
+        allow_mismatched: args.allow_mismatched,
+        quality_by_zoom: args.quality.clone(),
+        skip_errors: args.skip_errors,
+        viewer: args.with_viewer,
+        input_format: args.input_format,
+        decoder: None,
+        dedup: args.dedup,
+        linear_resize: args.linear_resize,
+        write_retries: args.write_retries,
+        sample: args.sample,
+        dump_intermediate: args.dump_intermediate.clone(),
+        strict: args.strict,
+        only: if args.only_overview {
+            Some(Phase::Overview)
+        } else if args.only_detail {
+            Some(Phase::Detail)
+        } else {
+            None
+        },
+        max_in_flight: args.max_in_flight.map(usize::from),
+        mbtiles: args.mbtiles.clone(),
+        region: args.region,
+        overview_res: args.overview_res,
+        interrupt: Some(Arc::clone(&INTERRUPTED)),
+        // set once everything's ready to go
+        deadline: None,
+        coordinates: args.coordinates.clone(),
+        bleed: args.bleed,
+        fast_overview: args.fast_overview,
+        overview_speed: args.overview_speed,
+        detail_speed: args.detail_speed,
+        overview_filter: args.overview_filter,
+        detail_filter: args.detail_filter,
+        precompress: args.precompress,
+        extensions: args.extensions.clone(),
+        transparent_threshold: args.transparent_threshold,
+        coverage_map: args.coverage_map.clone(),
+        // set from the inputs, below
+        more_inputs: Vec::new(),
+        overwrite_newer: args.overwrite_newer,
+        pad_partial: args.pad_partial,
+        staging: args.staging,
+        retina: args.retina,
+        prune_zooms: args.prune_zooms,
+        max_decode_bytes: args.max_decode_bytes,
+        max_decode_dimensions: args.max_decode_dimensions,
+        zoom_offset: args.zoom_offset,
+        tile_offset: args.tile_offset,
+        color_index: args.color_index.clone(),
+        skip_transparent: !args.no_skip_transparent,
+        space_check: !args.skip_space_check,
+        events: Some(EventSink::new({
+            let args = Arc::clone(&args);
+            move |event| on_event(&args, event)
+        })),
+    };
+
+    opts.img_ops.format = usable_format(&opts.img_ops);
+
+    // (name, input, output) for each pyramid to make; any more inputs go with the first
+    let layers = match args.input.split_first() {
+        Some((input, more)) => {
+            opts.more_inputs = more.to_vec();
+            vec![(None, input.clone(), args.output.clone())]
         }
-        Ok(())
-    })?;
+        None => args
+            .layer
+            .iter()
+            .map(|(name, dir)| (Some(name), dir.clone(), args.output.join(name)))
+            .collect(),
+    };
 
-    drop(mega);
+    if args.clean && !args.dry_run {
+        for (_, input, output) in &layers {
+            let inputs = [input]
+                .into_iter()
+                .chain(&opts.more_inputs)
+                .map(PathBuf::as_path)
+                .collect_vec();
+            clean(&inputs, output, args.yes)?;
+        }
+    }
 
-    info!("chopping individual bases into the remaining zoom levels...");
+    catch_interrupt();
+    opts.deadline = args.time_budget.map(|budget| Instant::now() + budget);
 
-    xys.par_iter().try_for_each(|(x, y)| -> Result<()> {
-        let Some(base) = base_lookup.get(&(i64::from(*x) + lx, i64::from(*y) + ly)) else {
-            return Ok(());
-        };
+    // zero is rayon's "pick for me"
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.jobs.map(usize::from).unwrap_or(0))
+        .build()?;
 
-        let img = image::open(base)
-            .with_context(|| anyhow!("reading {base:?} for remaining"))?;
-        if is_entirely_transparent(&img) {
-            debug!("skipping entirely transparent image {base:?}");
-            return Ok(());
+    for (name, input, output) in &layers {
+        if let Some(name) = name {
+            info!("tiling layer {name}...");
         }
-        let mut time_manip = 0;
-        let mut time_save = 0;
-        for neg_zoom in 0..=4 {
-            let mul = 2u32.pow(neg_zoom);
-            let tiles = tile_per_base / mul;
-            let step = tile_wh * mul;
-            // 8 means 2^8 = 256 tiles; (4096 / 256px/tile) = 16 tiles per screenshot
-            // 256/16 = 16 screenshots; i.e. -8 -> 7
-            let zoom = 9 - neg_zoom;
-            for ty in 0..tiles {
-                for tx in 0..tiles {
-                    let dx = x * tiles + tx;
-                    let dy = y * tiles + ty;
-                    let dest = format!("out/{zoom}/{dx}/{dy}.avif");
-                    if fs::metadata(&dest).is_ok() {
-                        continue;
-                    }
-
-                    let start = Instant::now();
-                    let crop = img.crop_imm(tx * step, ty * step, step, step);
-                    if is_entirely_transparent(&crop) {
-                        debug!("skipping transparent cropped tile at {x}x{y} -> {tx}x{ty}");
-                        continue;
-                    }
-                    let crop = crop.resize(tile_wh, tile_wh, FilterType::Lanczos3);
-                    time_manip += start.elapsed().as_nanos();
-                    let start = Instant::now();
-                    create_dir_and_save(dest, &crop, &img_ops)?;
-                    time_save += start.elapsed().as_nanos();
-                    debug!("saved {tx}x{ty} in {x}x{y} as {dx}x{dy}");
+        let stats = pool
+            .install(|| tile_directory(input, output, &opts))
+            .with_context(|| match name {
+                Some(name) => anyhow!("tiling layer {name}"),
+                None => anyhow!("tiling {input:?}"),
+            })?;
+        match args.log_format {
+            LogFormat::Text => {
+                if let Some(name) = name {
+                    println!("layer {name}:");
                 }
+                print_summary(&stats, args.dry_run, args.dedup);
             }
+            LogFormat::Json => print_summary_json(&stats, *name),
+        }
+        if stats.interrupted {
+            if INTERRUPTED.load(Ordering::SeqCst) {
+                return Err(anyhow!("interrupted"));
+            }
+            info!("out of time, so stopping here");
+            return Ok(());
         }
 
-        let time_manip = time_manip as f64 / 1e9;
-        let time_save = time_save as f64 / 1e9;
-        let complete = complete.fetch_add(1, Ordering::SeqCst) + 1;
-        info!("processed {complete}/{total_non_blank}: {base:?} (manip {time_manip:.2}s, save {time_save:.2}s)");
-
-        Ok(())
-    })?;
+        if args.verify {
+            info!("verifying {output:?}...");
+            let bad = pool.install(|| verify_tiles(output, &opts))?;
+            for (path, e) in &bad {
+                println!("bad tile {path:?}: {e:#}");
+            }
+            if !bad.is_empty() {
+                return Err(anyhow!("{} tiles in {output:?} didn't verify", bad.len()));
+            }
+        }
+    }
 
     info!("all done");
 
     Ok(())
 }
-
-/// only works for 8-bit images
-fn is_entirely_transparent(img: &DynamicImage) -> bool {
-    img.as_rgba8()
-        .map(|img| img.pixels().all(|p| p.0[3] == 0))
-        .unwrap_or(false)
-}
-
-fn create_dir_and_save(
-    path: impl AsRef<Path>,
-    img: &DynamicImage,
-    img_ops: &ImageOps,
-) -> Result<()> {
-    let path = path.as_ref();
-    fs::create_dir_all(
-        path.parent()
-            .ok_or_else(|| anyhow!("expected directory in path name, not {path:?}"))?,
-    )
-    .with_context(|| anyhow!("creating directories for {path:?}"))?;
-    let mut out = tempfile_fast::Sponge::new_for(path)?;
-    let enc = AvifEncoder::new_with_speed_quality(&mut out, img_ops.speed, img_ops.quality);
-    enc.write_image(img.as_bytes(), img.width(), img.height(), img.color())?;
-    out.commit()?;
-    Ok(())
-}
@@ -0,0 +1,77 @@
+use anyhow::{anyhow, Context, Result};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// what each zoom level of each base's tiles, and each overview tile, was last generated from
+///
+/// one line per (x, y, zoom), where x and y are the base's for detailed zoom levels, and the
+/// tile's for overview ones, tab separated, ending with a free-form stamp. lines are appended
+/// as bases complete, so an interrupted run loses nothing, and the file is compacted on open;
+/// a half-written last line just fails to parse, and its tiles are made again
+pub(crate) struct Manifest {
+    stamps: HashMap<(u32, u32, u32), String>,
+    out: Option<Mutex<File>>,
+}
+
+impl Manifest {
+    /// read the manifest at `path`, if there is one; `append` rewrites and then extends it
+    pub(crate) fn open(path: &Path, append: bool) -> Result<Manifest> {
+        let stamps = match fs::read_to_string(path) {
+            Ok(text) => text.lines().filter_map(parse_line).collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e).with_context(|| anyhow!("reading manifest {path:?}")),
+        };
+        if !append {
+            return Ok(Manifest { stamps, out: None });
+        }
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut compacted = tempfile_fast::Sponge::new_for(path)?;
+        for ((x, y, zoom), stamp) in &stamps {
+            writeln!(compacted, "{x}\t{y}\t{zoom}\t{stamp}")?;
+        }
+        compacted
+            .commit()
+            .with_context(|| anyhow!("compacting manifest {path:?}"))?;
+        let out = OpenOptions::new()
+            .append(true)
+            .open(path)
+            .with_context(|| anyhow!("opening manifest {path:?}"))?;
+        Ok(Manifest {
+            stamps,
+            out: Some(Mutex::new(out)),
+        })
+    }
+
+    /// whether the tiles at this base and zoom were last made from the same thing
+    pub(crate) fn is_current(&self, x: u32, y: u32, zoom: u32, stamp: &str) -> bool {
+        self.stamps.get(&(x, y, zoom)).map(String::as_str) == Some(stamp)
+    }
+
+    /// note that every tile at this base and zoom is now made from `stamp`
+    pub(crate) fn update(&self, x: u32, y: u32, zoom: u32, stamp: &str) -> Result<()> {
+        let Some(out) = &self.out else {
+            return Ok(());
+        };
+        if self.is_current(x, y, zoom, stamp) {
+            return Ok(());
+        }
+        let mut out = out.lock().expect("poisoned by another writer");
+        writeln!(out, "{x}\t{y}\t{zoom}\t{stamp}").context("appending to manifest")?;
+        Ok(())
+    }
+}
+
+fn parse_line(line: &str) -> Option<((u32, u32, u32), String)> {
+    let mut parts = line.splitn(4, '\t');
+    let x = parts.next()?.parse().ok()?;
+    let y = parts.next()?.parse().ok()?;
+    let zoom = parts.next()?.parse().ok()?;
+    let stamp = parts.next()?;
+    Some(((x, y, zoom), stamp.to_string()))
+}
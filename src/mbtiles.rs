@@ -0,0 +1,107 @@
+use anyhow::{anyhow, Context, Result};
+use std::collections::HashSet;
+use std::mem;
+use std::path::Path;
+use std::sync::Mutex;
+
+use rusqlite::{params, Connection};
+
+use crate::{tms_y, EncodedTile};
+
+/// tiles buffered before they're written in one transaction
+const BATCH: usize = 256;
+
+/// an sqlite database of tiles, in the mbtiles layout, instead of a directory of files
+///
+/// rows are addressed with tms y, flipped from the top-down y everything else uses
+pub(crate) struct MbTiles {
+    conn: Mutex<Connection>,
+    existing: HashSet<(u32, u32, u32)>,
+    pending: Mutex<Vec<EncodedTile>>,
+}
+
+impl MbTiles {
+    /// open or create the database at `path`, noting which tiles it already has
+    pub(crate) fn open(path: &Path) -> Result<MbTiles> {
+        let conn = Connection::open(path).with_context(|| anyhow!("opening {path:?}"))?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS metadata (name TEXT, value TEXT);
+             CREATE UNIQUE INDEX IF NOT EXISTS metadata_name ON metadata (name);
+             CREATE TABLE IF NOT EXISTS tiles (zoom_level INTEGER, tile_column INTEGER, tile_row INTEGER, tile_data BLOB);
+             CREATE UNIQUE INDEX IF NOT EXISTS tile_index ON tiles (zoom_level, tile_column, tile_row);",
+        )
+        .with_context(|| anyhow!("creating tables in {path:?}"))?;
+        let existing = conn
+            .prepare("SELECT zoom_level, tile_column, tile_row FROM tiles")?
+            .query_map(params![], |row| {
+                let zoom: u32 = row.get(0)?;
+                Ok((zoom, row.get(1)?, tms_y(zoom, row.get(2)?)))
+            })?
+            .collect::<Result<_, _>>()
+            .with_context(|| anyhow!("listing tiles in {path:?}"))?;
+        Ok(MbTiles {
+            conn: Mutex::new(conn),
+            existing,
+            pending: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// whether the tile was there when the database was opened
+    pub(crate) fn contains(&self, zoom: u32, x: u32, y: u32) -> bool {
+        self.existing.contains(&(zoom, x, y))
+    }
+
+    /// add or replace a tile; it may not be written until the next `flush`
+    pub(crate) fn insert(&self, zoom: u32, x: u32, y: u32, data: &[u8]) -> Result<()> {
+        let batch = {
+            let mut pending = self.pending.lock().expect("poisoned");
+            pending.push((zoom, x, y, data.to_vec()));
+            if pending.len() < BATCH {
+                return Ok(());
+            }
+            mem::take(&mut *pending)
+        };
+        self.write(batch)
+    }
+
+    /// write any buffered tiles
+    pub(crate) fn flush(&self) -> Result<()> {
+        let batch = mem::take(&mut *self.pending.lock().expect("poisoned"));
+        self.write(batch)
+    }
+
+    /// flush, and replace the metadata
+    pub(crate) fn finish(&self, metadata: &[(&str, String)]) -> Result<()> {
+        self.flush()?;
+        let mut conn = self.conn.lock().expect("poisoned");
+        let tx = conn.transaction()?;
+        {
+            let mut insert =
+                tx.prepare_cached("INSERT OR REPLACE INTO metadata (name, value) VALUES (?1, ?2)")?;
+            for (name, value) in metadata {
+                insert.execute(params![name, value])?;
+            }
+        }
+        tx.commit().context("writing metadata")?;
+        Ok(())
+    }
+
+    fn write(&self, batch: Vec<EncodedTile>) -> Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let mut conn = self.conn.lock().expect("poisoned");
+        let tx = conn.transaction()?;
+        {
+            let mut insert = tx.prepare_cached(
+                "INSERT OR REPLACE INTO tiles (zoom_level, tile_column, tile_row, tile_data) VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            for (zoom, x, y, data) in &batch {
+                insert.execute(params![zoom, x, tms_y(*zoom, *y), data])?;
+            }
+        }
+        tx.commit()
+            .with_context(|| anyhow!("writing {} tiles", batch.len()))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,157 @@
+use anyhow::{anyhow, Context, Result};
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use log::{info, warn};
+
+/// a base's coordinates, as found, and its file
+pub(crate) type Base = (i64, i64, PathBuf);
+
+/// the bases an unfinished run found, and which of them it's done, so a rerun needn't look
+/// for them or at them again
+///
+/// the first line is the run's options, the next the bases, one `base\tx\ty\tpath` each, then
+/// a `done\tx\ty` line is appended as each base completes. written whole before any appending,
+/// so a half-written last line can only be a done line, which is just done again
+pub(crate) struct Queue {
+    path: PathBuf,
+    bases: Vec<Base>,
+    done: HashSet<(i64, i64)>,
+    out: Option<Mutex<File>>,
+}
+
+impl Queue {
+    /// reuse the queue at `path` if it was left by a run with the same `key`, otherwise
+    /// `discover` the bases, and start a new one there if `write`
+    pub(crate) fn open(
+        path: &Path,
+        key: &str,
+        write: bool,
+        discover: impl FnOnce() -> Result<Vec<Base>>,
+    ) -> Result<Queue> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e).with_context(|| anyhow!("reading queue {path:?}")),
+        };
+        let mut lines = text.lines();
+        let (bases, done) = if lines.next() == Some(key) {
+            let mut bases = Vec::new();
+            let mut done = HashSet::new();
+            for line in lines {
+                match parse_line(line) {
+                    Some(Line::Base(base)) => bases.push(base),
+                    Some(Line::Done(xy)) => {
+                        done.insert(xy);
+                    }
+                    None => (),
+                }
+            }
+            info!(
+                "resuming from {path:?}: {} of {} images are already done",
+                done.len(),
+                bases.len()
+            );
+            (bases, done)
+        } else {
+            let bases = discover()?;
+            if write && !store(path, key, &bases)? {
+                // one left by another run would be picked up by the next with its options
+                if path.exists() {
+                    fs::remove_file(path).with_context(|| anyhow!("removing queue {path:?}"))?;
+                }
+                return Ok(Queue {
+                    path: path.to_path_buf(),
+                    bases,
+                    done: HashSet::new(),
+                    out: None,
+                });
+            }
+            (bases, HashSet::new())
+        };
+
+        let out = if write {
+            let out = OpenOptions::new()
+                .append(true)
+                .open(path)
+                .with_context(|| anyhow!("opening queue {path:?}"))?;
+            Some(Mutex::new(out))
+        } else {
+            None
+        };
+        Ok(Queue {
+            path: path.to_path_buf(),
+            bases,
+            done,
+            out,
+        })
+    }
+
+    pub(crate) fn bases(&self) -> Vec<Base> {
+        self.bases.clone()
+    }
+
+    /// whether this base was finished by the run which left the queue
+    pub(crate) fn is_done(&self, x: i64, y: i64) -> bool {
+        self.done.contains(&(x, y))
+    }
+
+    /// note that this base is finished
+    pub(crate) fn done(&self, x: i64, y: i64) -> Result<()> {
+        let Some(out) = &self.out else {
+            return Ok(());
+        };
+        let mut out = out.lock().expect("poisoned by another writer");
+        writeln!(out, "done\t{x}\t{y}").context("appending to queue")?;
+        Ok(())
+    }
+
+    /// every base is done, so the next run should look for them afresh
+    pub(crate) fn finish(self) -> Result<()> {
+        if self.out.is_none() {
+            return Ok(());
+        }
+        fs::remove_file(&self.path).with_context(|| anyhow!("removing queue {:?}", self.path))
+    }
+}
+
+/// write a new queue, unless a path can't be written in it; returns whether it was
+fn store(path: &Path, key: &str, bases: &[Base]) -> Result<bool> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut out = tempfile_fast::Sponge::new_for(path)?;
+    writeln!(out, "{key}")?;
+    for (x, y, file) in bases {
+        match file.to_str() {
+            Some(name) if !name.contains('\n') => writeln!(out, "base\t{x}\t{y}\t{name}")?,
+            _ => {
+                warn!("not keeping a queue, as {file:?} can't be written in one");
+                return Ok(false);
+            }
+        }
+    }
+    out.commit()
+        .with_context(|| anyhow!("writing queue {path:?}"))?;
+    Ok(true)
+}
+
+enum Line {
+    Base(Base),
+    Done((i64, i64)),
+}
+
+fn parse_line(line: &str) -> Option<Line> {
+    let mut parts = line.splitn(4, '\t');
+    let kind = parts.next()?;
+    let x = parts.next()?.parse().ok()?;
+    let y = parts.next()?.parse().ok()?;
+    match kind {
+        "base" => Some(Line::Base((x, y, PathBuf::from(parts.next()?)))),
+        "done" => Some(Line::Done((x, y))),
+        _ => None,
+    }
+}
@@ -0,0 +1,59 @@
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+/// hands out up to `permits` at once, to callers of `acquire_helping`
+pub(crate) struct Semaphore {
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+/// returned to the semaphore on drop
+pub(crate) struct Permit<'s> {
+    semaphore: &'s Semaphore,
+    count: usize,
+}
+
+impl Semaphore {
+    pub(crate) fn new(permits: usize) -> Semaphore {
+        Semaphore {
+            available: Mutex::new(permits),
+            released: Condvar::new(),
+        }
+    }
+
+    /// wait for `count` permits, running the rayon pool's other work meanwhile, so waiting
+    /// never holds up the holders, whatever they're waiting on in the pool themselves
+    ///
+    /// only for a thread which isn't itself inside work that needs a permit, e.g. one handing
+    /// that work out; waiting anywhere else risks it being the work that would release one
+    pub(crate) fn acquire_helping(&self, count: usize) -> Permit<'_> {
+        loop {
+            let mut available = self.available.lock().expect("poisoned");
+            if *available >= count {
+                *available -= count;
+                return Permit {
+                    semaphore: self,
+                    count,
+                };
+            }
+            drop(available);
+            if rayon::yield_now() != Some(rayon::Yield::Executed) {
+                // nothing to help with, so some holder is busy, and will release soon enough
+                let available = self.available.lock().expect("poisoned");
+                if *available < count {
+                    let _ = self
+                        .released
+                        .wait_timeout(available, Duration::from_millis(50))
+                        .expect("poisoned");
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        *self.semaphore.available.lock().expect("poisoned") += self.count;
+        self.semaphore.released.notify_all();
+    }
+}
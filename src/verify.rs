@@ -0,0 +1,109 @@
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use image::ImageFormat;
+use rayon::prelude::*;
+
+use crate::{progress_bar, OutputFormat, TileError, TileOptions};
+
+/// re-read every tile under `output`, returning those which don't decode, and why; anything
+/// hidden, i.e. starting with a `.`, isn't a tile
+///
+/// there's no avif decoder built in, so avif tiles only have their container checked, which
+/// still catches truncation
+pub fn verify_tiles(
+    output: &Path,
+    opts: &TileOptions,
+) -> Result<Vec<(PathBuf, TileError)>, TileError> {
+    let ext = opts.img_ops.format.extension();
+    let mut tiles = Vec::new();
+    let mut dirs = vec![output.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir).with_context(|| anyhow!("listing {dir:?}"))? {
+            let entry = entry?;
+            // the manifest, queue and shrunk copies beside the tiles, not tiles themselves
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                dirs.push(path);
+            } else if path.extension().is_some_and(|e| e == ext) {
+                tiles.push(path);
+            }
+        }
+    }
+
+    let bar = progress_bar(opts.progress, "verify", tiles.len());
+    let bad = tiles
+        .into_par_iter()
+        .filter_map(|path| {
+            let result = fs::read(&path)
+                .context("reading")
+                .and_then(|data| check(&data, opts.img_ops.format));
+            bar.inc(1);
+            result.err().map(|e| (path, e.into()))
+        })
+        .collect();
+    bar.finish_and_clear();
+    Ok(bad)
+}
+
+fn check(data: &[u8], format: OutputFormat) -> Result<()> {
+    let format = match format {
+        OutputFormat::Avif => return check_avif(data),
+        OutputFormat::Png => ImageFormat::Png,
+        OutputFormat::Webp => ImageFormat::WebP,
+        OutputFormat::Jpeg => ImageFormat::Jpeg,
+    };
+    image::load_from_memory_with_format(data, format)?;
+    Ok(())
+}
+
+/// the top level boxes must exactly fill the file, starting with an avif `ftyp`, and include
+/// some image data
+fn check_avif(mut data: &[u8]) -> Result<()> {
+    let mut first = true;
+    let mut seen_data = false;
+    while !data.is_empty() {
+        if data.len() < 8 {
+            return Err(anyhow!("{} stray bytes at the end", data.len()));
+        }
+        let kind = data[4..8].escape_ascii().to_string();
+        let (header, size) = match u32::from_be_bytes(data[..4].try_into()?) {
+            0 => (8, data.len() as u64),
+            1 if data.len() < 16 => return Err(anyhow!("truncated {kind:?} box header")),
+            1 => (16, u64::from_be_bytes(data[8..16].try_into()?)),
+            size => (8, u64::from(size)),
+        };
+        if size < header || size > data.len() as u64 {
+            return Err(anyhow!(
+                "{kind:?} box claims {size} bytes, but {} are left",
+                data.len()
+            ));
+        }
+        let contents = &data[header as usize..size as usize];
+        if first {
+            // the major brand, then a version, then the compatible brands
+            let brands = contents.get(8..).unwrap_or_default();
+            if kind != "ftyp"
+                || !(contents.starts_with(b"avif") || brands.chunks(4).any(|b| b == b"avif"))
+            {
+                return Err(anyhow!("doesn't start with an avif ftyp box"));
+            }
+            first = false;
+        }
+        if kind == "mdat" && !contents.is_empty() {
+            seen_data = true;
+        }
+        data = &data[size as usize..];
+    }
+    if first {
+        return Err(anyhow!("empty file"));
+    }
+    if !seen_data {
+        return Err(anyhow!("no image data"));
+    }
+    Ok(())
+}